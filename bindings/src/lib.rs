@@ -13,7 +13,8 @@ use unity_scene_repacker::rabex::files::bundlefile::CompressionType;
 use unity_scene_repacker::rabex::tpk::TpkTypeTreeBlob;
 use unity_scene_repacker::rabex::typetree::typetree_cache::sync::TypeTreeCache;
 use unity_scene_repacker::{
-    GameFiles, MonobehaviourTypetreeMode, RepackSettings, Stats, monobehaviour_typetree_export,
+    GameFiles, MonobehaviourTypetreeMode, PackSettings, RepackSettings, RootTransforms,
+    SceneObject, Stats, monobehaviour_typetree_export,
 };
 
 #[repr(C)]
@@ -109,12 +110,13 @@ fn export_inner(
 
     let compression = CompressionType::None;
 
-    let scene_objects: IndexMap<String, Vec<String>> =
+    let scene_objects: IndexMap<String, Vec<SceneObject>> =
         serde_json::from_str(scene_objects).context("error parsing the objects json")?;
 
     let repack_settings = RepackSettings {
         scene_objects,
         extra_objects: IndexMap::new(),
+        scene_targets: IndexMap::new(),
     };
 
     let disable = true;
@@ -163,6 +165,8 @@ fn export_inner(
         repack_settings,
         disable,
         matches!(mode, Mode::AssetBundle),
+        RootTransforms::default(),
+        false,
     )?;
 
     let enable_typetree = false; // TODO: make this configurable / infer if necessary
@@ -175,7 +179,9 @@ fn export_inner(
             &env.tpk,
             &unity_version,
             repack_scenes.as_mut_slice(),
+            extra_objects,
             compression,
+            &PackSettings::default(),
         )
         .context("trying to repack bundle")?,
         Mode::AssetBundle => unity_scene_repacker::pack_to_asset_bundle(
@@ -187,6 +193,7 @@ fn export_inner(
             extra_objects,
             compression,
             enable_typetree,
+            &PackSettings::default(),
         )?,
         Mode::AssetBundleShallow => unreachable!(),
     };
@@ -0,0 +1,77 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::Args;
+use paris::{error, info, success, warn};
+use unity_scene_repacker::diff::{self, ObjectStatus};
+
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    /// Directory of the old game version
+    #[arg(long)]
+    old: PathBuf,
+    /// Directory of the new game version
+    #[arg(long)]
+    new: PathBuf,
+
+    /// Path to JSON file, containing a map of scene name to a list of gameobject paths to compare
+    #[arg(long)]
+    #[arg(alias = "objects")]
+    scene_objects: PathBuf,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Write a copy of the scene objects file, with moved objects updated and missing ones removed
+    #[arg(long)]
+    migrate: Option<PathBuf>,
+}
+
+pub fn run(args: DiffArgs) -> Result<()> {
+    let scene_objects = crate::read_scene_objects(&args.scene_objects)?;
+
+    let old = crate::open_env(&crate::resolve_game_dir(args.old)?)?;
+    let new = crate::open_env(&crate::resolve_game_dir(args.new)?)?;
+
+    let diffs = diff::diff_scene_objects(&old, &new, &scene_objects)?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diffs)?);
+    } else {
+        for diff in &diffs {
+            let scene_path = format!("{}/{}", diff.scene, diff.path);
+            match &diff.status {
+                ObjectStatus::Unchanged => info!("<b>{scene_path}</b>: unchanged"),
+                ObjectStatus::MissingInOld => {
+                    warn!("<b>{scene_path}</b>: does not exist in the old game version")
+                }
+                ObjectStatus::Missing => error!("<b>{scene_path}</b>: missing"),
+                ObjectStatus::Moved { new_paths } => {
+                    warn!("<b>{scene_path}</b>: moved to");
+                    for new_path in new_paths {
+                        warn!("  - {new_path}");
+                    }
+                }
+                ObjectStatus::ChangedComponents { added, removed } => {
+                    warn!("<b>{scene_path}</b>: components changed");
+                    for component in added {
+                        warn!("  + {component}");
+                    }
+                    for component in removed {
+                        warn!("  - {component}");
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(path) = args.migrate {
+        let migrated = diff::migrate_scene_objects(&scene_objects, &diffs);
+        std::fs::write(&path, serde_json::to_string_pretty(&migrated)?)
+            .with_context(|| format!("Could not write migrated objects to '{}'", path.display()))?;
+        success!("Wrote migrated scene objects to <b>{}</b>", path.display());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::Args;
+use paris::{info, success, warn};
+use rabex::tpk::TpkTypeTreeBlob;
+use rabex::typetree::typetree_cache::sync::TypeTreeCache;
+use unity_scene_repacker::bundle_diff::{self, ObjectChange};
+use unity_scene_repacker::inspect;
+
+#[derive(Args, Debug)]
+pub struct DiffBundlesArgs {
+    /// Path to the old bundle
+    old: PathBuf,
+    /// Path to the new bundle
+    new: PathBuf,
+
+    /// Print the differences as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+pub fn run(args: DiffBundlesArgs) -> Result<()> {
+    let tpk = TypeTreeCache::new(TpkTypeTreeBlob::embedded());
+
+    let read = |path: &PathBuf| -> Result<_> {
+        let data =
+            std::fs::read(path).with_context(|| format!("Could not read '{}'", path.display()))?;
+        inspect::read_bundle(&data).with_context(|| format!("In '{}'", path.display()))
+    };
+    let old = read(&args.old)?;
+    let new = read(&args.new)?;
+
+    let diff = bundle_diff::diff_bundles(&old, &new, &tpk)?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+        return Ok(());
+    }
+
+    if diff.is_empty() {
+        success!("Bundles are identical");
+        return Ok(());
+    }
+
+    for file in &diff.files_added {
+        info!("+ file {file}");
+    }
+    for file in &diff.files_removed {
+        warn!("- file {file}");
+    }
+    for path in &diff.container_added {
+        info!("+ container {path}");
+    }
+    for path in &diff.container_removed {
+        warn!("- container {path}");
+    }
+    for externals in &diff.externals {
+        for external in &externals.added {
+            info!("+ external {external} in {}", externals.file);
+        }
+        for external in &externals.removed {
+            warn!("- external {external} in {}", externals.file);
+        }
+    }
+
+    for object in &diff.objects {
+        let name = format!(
+            "{} {} ({}, {})",
+            object.file, object.object, object.class, object.path_id
+        );
+        match &object.change {
+            ObjectChange::Added => info!("+ object {name}"),
+            ObjectChange::Removed => warn!("- object {name}"),
+            ObjectChange::Modified { fields } => {
+                info!("~ object {name}");
+                for field in fields {
+                    info!("    {}: {} -> {}", field.field, field.old, field.new);
+                }
+            }
+            ObjectChange::DataChanged => info!("~ object {name}, could not be decoded"),
+        }
+    }
+
+    Ok(())
+}
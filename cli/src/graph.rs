@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::Args;
+use indexmap::IndexMap;
+use paris::success;
+use unity_scene_repacker::graph;
+use unity_scene_repacker::{RepackSettings, RootTransforms};
+
+use crate::GameArgs;
+
+#[derive(Args, Debug)]
+pub struct GraphArgs {
+    #[clap(flatten)]
+    game: GameArgs,
+
+    /// Path to JSON file, containing a map of scene name to a list of gameobject paths to include
+    #[arg(long)]
+    #[arg(alias = "objects")]
+    scene_objects: PathBuf,
+
+    #[arg(long, default_value = "dot")]
+    format: GraphFormat,
+
+    /// File to write the graph to. Prints to stdout if not specified
+    #[arg(long, short = 'o')]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, clap::ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz
+    Dot,
+    Graphml,
+}
+
+pub fn run(args: GraphArgs) -> Result<()> {
+    let env = crate::open_env(&crate::locate_game(args.game)?)?;
+
+    let repack_settings = RepackSettings {
+        scene_objects: crate::read_scene_objects(&args.scene_objects)?,
+        extra_objects: IndexMap::new(),
+        scene_targets: IndexMap::new(),
+    };
+    let (scenes, _) = unity_scene_repacker::repack_scenes(
+        &env,
+        repack_settings,
+        true,
+        false,
+        RootTransforms::Local,
+        false,
+    )?;
+
+    let graph = graph::dependency_graph(&scenes, &env.tpk)?;
+    let out = match args.format {
+        GraphFormat::Dot => graph.to_dot(),
+        GraphFormat::Graphml => graph.to_graphml(),
+    };
+
+    match args.output {
+        Some(path) => {
+            std::fs::write(&path, out)
+                .with_context(|| format!("Could not write to '{}'", path.display()))?;
+            success!(
+                "Wrote graph with {} objects and {} references to <b>{}</b>",
+                graph.nodes.len(),
+                graph.edges.len(),
+                path.display()
+            );
+        }
+        None => print!("{out}"),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::Args;
+use paris::success;
+use unity_scene_repacker::hierarchy;
+
+use crate::GameArgs;
+
+#[derive(Args, Debug)]
+pub struct HierarchyArgs {
+    #[clap(flatten)]
+    game: GameArgs,
+
+    /// Name of the scene to export
+    #[arg(long)]
+    scene: String,
+
+    /// Only export the subtree at this path
+    #[arg(long)]
+    root: Option<String>,
+
+    /// Only export the hierarchy and component classes, without decoding the component fields
+    #[arg(long)]
+    no_fields: bool,
+
+    /// File to write the JSON to. Prints to stdout if not specified
+    #[arg(long, short = 'o')]
+    output: Option<PathBuf>,
+}
+
+pub fn run(args: HierarchyArgs) -> Result<()> {
+    let env = crate::open_env(&crate::locate_game(args.game)?)?;
+
+    let nodes =
+        hierarchy::export_hierarchy(&env, &args.scene, args.root.as_deref(), !args.no_fields)?;
+    let json = serde_json::to_string_pretty(&nodes)?;
+
+    match args.output {
+        Some(path) => {
+            std::fs::write(&path, json)
+                .with_context(|| format!("Could not write to '{}'", path.display()))?;
+            success!(
+                "Exported hierarchy of {} to <b>{}</b>",
+                args.scene,
+                path.display()
+            );
+        }
+        None => println!("{json}"),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use clap::Args;
+use paris::{error, info, success};
+use rabex::tpk::TpkTypeTreeBlob;
+use rabex::typetree::typetree_cache::sync::TypeTreeCache;
+use rabex_env::Environment;
+use unity_scene_repacker::inspect::{self, VerificationError};
+
+use crate::utils::friendly_size;
+
+#[derive(Args, Debug)]
+pub struct InspectArgs {
+    /// Path to the bundle file
+    bundle: PathBuf,
+
+    /// Print the contents as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Skip checking that all references in the bundle resolve
+    #[arg(long)]
+    no_verify: bool,
+
+    /// Game the bundle was packed from. Needed to check the fields of MonoBehaviours in bundles without typetrees.
+    #[arg(long)]
+    game_dir: Option<PathBuf>,
+}
+
+pub fn run(args: InspectArgs) -> Result<()> {
+    let tpk = TypeTreeCache::new(TpkTypeTreeBlob::embedded());
+    let env = args
+        .game_dir
+        .map(|game_dir| crate::open_env(&crate::resolve_game_dir(game_dir)?))
+        .transpose()?;
+
+    let data = std::fs::read(&args.bundle)
+        .with_context(|| format!("Could not read '{}'", args.bundle.display()))?;
+    let entries = inspect::read_bundle(&data)?;
+    let bundle_info = inspect::inspect_bundle(&entries, &tpk)?;
+    let errors = match args.no_verify {
+        true => Vec::new(),
+        false => match &env {
+            Some(env) => inspect::verify_bundle_with_scripts(&entries, env)?,
+            None => inspect::verify_bundle(&entries, &tpk)?,
+        },
+    };
+
+    if args.json {
+        let json = serde_json::json!({
+            "bundle": bundle_info,
+            "errors": (!args.no_verify).then_some(&errors),
+        });
+        println!("{}", serde_json::to_string_pretty(&json)?);
+    } else {
+        for file in &bundle_info.files {
+            info!("<b>{}</b> <i>({})</i>", file.name, friendly_size(file.size));
+            if let Some(externals) = &file.externals {
+                for external in externals {
+                    info!("  external: {external}");
+                }
+            }
+            for (class, count) in &file.objects_by_class {
+                info!("  {count:>6} {class}");
+            }
+        }
+
+        if let Some(asset_bundle) = &bundle_info.asset_bundle {
+            println!();
+            info!("AssetBundle <b>{}</b>", asset_bundle.name);
+            for scene in &asset_bundle.scenes {
+                info!("  scene: {scene}");
+            }
+            for entry in &asset_bundle.container {
+                info!("  {} -> ({}, {})", entry.path, entry.file_id, entry.path_id);
+            }
+            for dependency in &asset_bundle.dependencies {
+                info!("  dependency: {dependency}");
+            }
+        }
+
+        if !args.no_verify {
+            println!();
+            print_verification(&errors);
+        }
+    }
+
+    if !errors.is_empty() {
+        bail!("{} unresolved references in bundle", errors.len());
+    }
+
+    Ok(())
+}
+
+/// Checks the freshly written output bundle, see [`inspect::verify_bundle_with_scripts`]
+pub fn verify_output(path: &Path, env: &Environment) -> Result<()> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("Could not read '{}' for verification", path.display()))?;
+    let errors = inspect::verify_bundle_data(&data, env)?;
+    print_verification(&errors);
+    if !errors.is_empty() {
+        bail!("{} unresolved references in bundle", errors.len());
+    }
+    Ok(())
+}
+
+fn print_verification(errors: &[VerificationError]) {
+    if errors.is_empty() {
+        success!("All references in the bundle resolve");
+    }
+    for error in errors {
+        error!("{error}");
+    }
+}
@@ -1,4 +1,9 @@
 mod completion;
+mod diff;
+mod diff_bundles;
+mod graph;
+mod hierarchy;
+mod inspect;
 mod locate;
 mod logger;
 mod utils;
@@ -7,7 +12,7 @@ mod utils;
 mod py;
 
 use anyhow::{Context, Result, bail, ensure};
-use clap::{Args, CommandFactory as _, Parser};
+use clap::{Args, CommandFactory as _, Parser, Subcommand};
 use clap_complete::ArgValueCompleter;
 use indexmap::{IndexMap, IndexSet};
 use paris::{error, info, success, warn};
@@ -17,16 +22,27 @@ use rabex::typetree::typetree_cache::sync::TypeTreeCache;
 use rabex_env::Environment;
 use std::ffi::{OsStr, OsString};
 use std::fs::{DirBuilder, File};
-use std::io::BufWriter;
-use std::path::PathBuf;
+use std::io::{BufWriter, Cursor};
+use std::path::{Path, PathBuf};
 use std::time::Instant;
-use unity_scene_repacker::{GameFiles, RepackSettings, Stats};
+use unity_scene_repacker::manifest::{self, Hash128, ManifestEntry};
+use unity_scene_repacker::size_report::{Budget, SizeReport};
+use unity_scene_repacker::{
+    BundleSplit, GameFiles, PackSettings, PathIdStrategy, RepackSettings, RootContainer,
+    SceneObject, SceneTarget, Stats,
+};
 
 use crate::utils::friendly_size;
 
 #[derive(Parser, Debug)]
-#[command(version)]
+#[command(
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Arguments {
+    #[command(subcommand)]
+    command: Option<Command>,
     #[clap(flatten)]
     game: GameArgs,
     #[clap(flatten)]
@@ -62,6 +78,11 @@ struct RepackArgs {
     ///   ]
     /// }
     /// ```
+    /// To keep the parents of an object as placeholders with only a Transform (and optionally
+    /// some components), use `{ "path": "_UI/Canvas/Button", "keep_ancestors": ["Canvas"] }`.
+    /// Paths starting with `!` remove that subtree from the other objects, e.g. `"!Boss Arena/Music Region"`,
+    /// and `{ "path": "Boss", "exclude_components": ["HealthManager"] }` removes components.
+    /// `"*"` keeps the whole scene, minus the exclusions.
     #[arg(long)]
     #[arg(alias = "objects")]
     scene_objects: Option<PathBuf>,
@@ -87,6 +108,15 @@ struct OutputArgs {
     #[arg(long, default_value = "false")]
     disable: bool,
 
+    /// How to handle the transform of nested objects that become roots
+    #[arg(long, default_value = "local")]
+    root_transforms: RootTransforms,
+
+    /// Keep render, lightmap, occlusion culling and navmesh settings of the scenes, so that they look like the original.
+    /// Only useful for `--mode scene`
+    #[arg(long)]
+    keep_scene_settings: bool,
+
     /// Compression level to apply
     #[arg(long, default_value = "lzma")]
     compression: Compression,
@@ -97,6 +127,112 @@ struct OutputArgs {
     /// Name to give the assetbundle. Should be unique for your game.
     #[arg(long)]
     bundle_name: Option<String>,
+
+    /// Check that all references in the written bundle resolve
+    #[arg(long)]
+    verify: bool,
+
+    /// Additionally write a bundle with this name next to the output, containing an `AssetBundleManifest` that lists the output.
+    /// `--mode multi` always writes one, named after `--bundle-name`
+    #[arg(long, value_name = "NAME")]
+    manifest: Option<String>,
+
+    /// Print which requested objects, classes and scenes contribute most to the output size
+    #[arg(long)]
+    size_report: bool,
+
+    /// Write the size report as JSON to the given path
+    #[arg(long)]
+    size_report_json: Option<PathBuf>,
+
+    #[command(flatten)]
+    budget: BudgetArgs,
+
+    /// Merge the objects of all scenes into a single scene with this name, so that one `LoadScene` loads everything.
+    /// Only for `--mode scene`
+    #[arg(long, conflicts_with = "scene_groups")]
+    merge_scenes: Option<String>,
+
+    /// Path to JSON file, containing a map of merged scene name to the scenes to merge into it.
+    /// Only for `--mode scene`
+    /// ```json
+    /// {
+    ///   "Enemies": ["Fungus1_12", "Crossroads_04"]
+    /// }
+    /// ```
+    #[arg(long)]
+    scene_groups: Option<PathBuf>,
+
+    /// Move all objects of a scene under a new root GameObject, named after the scene or the given name.
+    /// Only for `--mode scene`
+    #[arg(long, num_args = 0..=1, value_name = "NAME")]
+    root_container: Option<Option<String>>,
+
+    /// Derive the PathIds of objects in asset bundles and merged scenes from their scene and original PathId,
+    /// so that they stay the same when other objects are added or removed
+    #[arg(long)]
+    stable_path_ids: bool,
+
+    /// Keep a single copy of objects with identical content, like the same material used in several scenes.
+    /// Only for `--mode asset` and `--mode hybrid`
+    #[arg(long)]
+    dedupe_objects: bool,
+
+    /// Scenes whose objects are packed as assets you can load using `LoadAsset`, all other scenes are packed as scenes.
+    /// Only for `--mode hybrid`
+    #[arg(long, value_delimiter = ',', value_name = "SCENE")]
+    asset_scenes: Vec<String>,
+
+    /// How to split the objects into bundles.
+    /// Only for `--mode multi`
+    #[arg(long, default_value = "scene")]
+    split: Split,
+
+    /// Path to JSON file, containing a map of bundle name to the scenes or `scene/path` objects in it.
+    /// Objects not in any group get a bundle per scene. Only for `--mode multi`
+    /// ```json
+    /// {
+    ///   "bosses": ["Fungus1_12/Boss", "Crossroads_04/Giant Fly"]
+    /// }
+    /// ```
+    #[arg(long, conflicts_with = "split")]
+    bundle_groups: Option<PathBuf>,
+}
+
+/// Fail before writing the bundle if the pruned objects exceed these limits.
+/// Sizes are uncompressed and accept units like `KiB` and `MiB`.
+#[derive(Args, Debug)]
+#[clap(next_help_heading = "Size budget")]
+struct BudgetArgs {
+    /// Maximum total size of all objects, before compression and without the file headers
+    #[arg(long, value_parser = utils::parse_size)]
+    max_object_size: Option<usize>,
+    /// Maximum size attributed to a single requested object
+    #[arg(long, value_parser = utils::parse_size)]
+    max_root_size: Option<usize>,
+    /// Maximum total size of all objects of one class
+    #[arg(long, value_parser = utils::parse_size)]
+    max_class_size: Option<usize>,
+    /// Maximum number of objects
+    #[arg(long)]
+    max_objects: Option<usize>,
+    /// Maximum total size of objects not reachable from any requested object
+    #[arg(long, value_parser = utils::parse_size)]
+    max_unattributed_size: Option<usize>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Compare the requested objects between two versions of a game
+    Diff(diff::DiffArgs),
+    /// List the contents of a bundle and verify that all references in it resolve
+    Inspect(inspect::InspectArgs),
+    /// Show the structural differences between two bundles
+    DiffBundles(diff_bundles::DiffBundlesArgs),
+    /// Export the GameObject hierarchy of a scene with all component data as JSON
+    Hierarchy(hierarchy::HierarchyArgs),
+    /// Export the reference graph of the repacked objects as Graphviz DOT or GraphML
+    Graph(graph::GraphArgs),
 }
 
 /// What kind of asset bundle to build
@@ -110,6 +246,31 @@ pub enum Mode {
     /// An asset bundle containing individual assets you can load using `LoadAsset`.
     /// The bundle is completely empty, and only references the original game level files.
     AssetShallow,
+    /// A scene asset bundle which also contains assets you can load using `LoadAsset`.
+    /// The scenes listed in `--asset-scenes` are packed like `--mode asset`, the others like `--mode scene`.
+    Hybrid,
+    /// Several asset bundles like `--mode asset`, split by `--split` or `--bundle-groups`.
+    /// Objects used by more than one bundle are moved into a shared bundle, and an `AssetBundleManifest` bundle lists their dependencies.
+    /// `--output` is the directory to write the bundles to.
+    Multi,
+}
+
+#[derive(Debug, Clone, clap::ValueEnum)]
+pub enum Split {
+    /// One bundle per scene
+    Scene,
+    /// One bundle per requested object
+    Root,
+}
+
+#[derive(Debug, Clone, clap::ValueEnum)]
+pub enum RootTransforms {
+    /// Keep the transform relative to the original parent
+    Local,
+    /// Bake the transforms of the original parents in, so the object keeps its position in the world
+    World,
+    /// Reset the position, rotation and scale
+    Identity,
 }
 
 #[derive(Debug, Clone, clap::ValueEnum)]
@@ -145,23 +306,17 @@ pub fn main(args: Vec<OsString>) {
 fn run(args: Vec<OsString>) -> Result<()> {
     let args = Arguments::parse_from(args);
 
-    let game_dir = match args.game.game_dir {
-        Some(game_dir) => {
-            ensure!(
-                game_dir.exists(),
-                "Game directory '{}' does not exist",
-                game_dir.display()
-            );
-            match locate::find_unity_data_dir(&game_dir) {
-                Ok(Some(data_dir)) => data_dir,
-                _ => game_dir,
-            }
-        }
-        None => {
-            let game = args.game.steam_game.unwrap();
-            locate::locate_steam_game(&game)?
-        }
-    };
+    if let Some(command) = args.command {
+        return match command {
+            Command::Diff(args) => diff::run(args),
+            Command::Inspect(args) => inspect::run(args),
+            Command::DiffBundles(args) => diff_bundles::run(args),
+            Command::Hierarchy(args) => hierarchy::run(args),
+            Command::Graph(args) => graph::run(args),
+        };
+    }
+
+    let game_dir = locate_game(args.game)?;
 
     #[cfg(feature = "dhat-heap")]
     let _profiler = dhat::Profiler::new_heap();
@@ -171,13 +326,8 @@ fn run(args: Vec<OsString>) -> Result<()> {
     let scene_objects = args
         .repack
         .scene_objects
-        .as_ref()
-        .map(|path| -> Result<IndexMap<String, Vec<String>>> {
-            let preloads = std::fs::read_to_string(path).with_context(|| {
-                format!("couldn't find scene objects json '{}'", path.display())
-            })?;
-            json5::from_str(&preloads).context("error parsing the scene objects json")
-        })
+        .as_deref()
+        .map(read_scene_objects)
         .transpose()?
         .unwrap_or_default();
     let extra_objects = args
@@ -216,9 +366,69 @@ fn run(args: Vec<OsString>) -> Result<()> {
             if obj_count == 1 { "" } else { "s" }
         );
     }
+    let scene_groups = match (&args.output.merge_scenes, &args.output.scene_groups) {
+        (Some(name), _) => {
+            // scenes packed as assets cannot be part of a merged scene
+            let scenes = scene_objects
+                .keys()
+                .filter(|scene| {
+                    !(matches!(args.output.mode, Mode::Hybrid)
+                        && args.output.asset_scenes.contains(scene))
+                })
+                .cloned()
+                .collect();
+            IndexMap::from_iter([(name.clone(), scenes)])
+        }
+        (None, Some(path)) => {
+            let scene_groups = std::fs::read_to_string(path)
+                .with_context(|| format!("couldn't find scene groups json '{}'", path.display()))?;
+            json5::from_str(&scene_groups).context("error parsing the scene groups json")?
+        }
+        (None, None) => IndexMap::new(),
+    };
+    let has_scenes = matches!(args.output.mode, Mode::Scene | Mode::Hybrid);
+    if !scene_groups.is_empty() && !has_scenes {
+        warn!("Merging scenes only has an effect for --mode scene and hybrid");
+    }
+    if args.output.root_container.is_some() && !has_scenes {
+        warn!("--root-container only has an effect for --mode scene and hybrid");
+    }
+    let split = match &args.output.bundle_groups {
+        Some(path) => {
+            let bundle_groups = std::fs::read_to_string(path).with_context(|| {
+                format!("couldn't find bundle groups json '{}'", path.display())
+            })?;
+            BundleSplit::Groups(
+                json5::from_str(&bundle_groups).context("error parsing the bundle groups json")?,
+            )
+        }
+        None => match args.output.split {
+            Split::Scene => BundleSplit::PerScene,
+            Split::Root => BundleSplit::PerRoot,
+        },
+    };
+    if args.output.bundle_groups.is_some() && !matches!(args.output.mode, Mode::Multi) {
+        warn!("--bundle-groups only has an effect for --mode multi");
+    }
+    let scene_targets = match args.output.mode {
+        Mode::Hybrid => args
+            .output
+            .asset_scenes
+            .iter()
+            .map(|scene| (scene.clone(), SceneTarget::Asset))
+            .collect(),
+        _ => {
+            if !args.output.asset_scenes.is_empty() {
+                warn!("--asset-scenes only has an effect for --mode hybrid");
+            }
+            IndexMap::new()
+        }
+    };
+
     let repack_settings = RepackSettings {
         scene_objects,
         extra_objects,
+        scene_targets,
     };
 
     if repack_settings.is_empty() {
@@ -257,11 +467,9 @@ fn run(args: Vec<OsString>) -> Result<()> {
     };
 
     if let Mode::AssetShallow = args.output.mode {
-        let mut out = BufWriter::new(
-            File::create(&args.output.output).context("Could not write to output file")?,
-        );
+        let mut out = Cursor::new(Vec::new());
 
-        unity_scene_repacker::pack_to_shallow_asset_bundle(
+        let stats = unity_scene_repacker::pack_to_shallow_asset_bundle(
             &env,
             &mut out,
             name,
@@ -269,7 +477,24 @@ fn run(args: Vec<OsString>) -> Result<()> {
             compression,
         )?;
 
-        let new_size = out.get_ref().metadata()?.len() as usize;
+        let new_size = write_output(&args.output.output, out)?;
+
+        if args.output.verify {
+            inspect::verify_output(&args.output.output, &env)?;
+        }
+        info!(
+            "CRC <b>{}</b>, hash {}",
+            stats.checksum.crc, stats.checksum.hash
+        );
+        if let Some(manifest_name) = &args.output.manifest {
+            write_manifest(
+                &[(args.output.output.clone(), stats.checksum.hash)],
+                manifest_name,
+                &env,
+                &tpk_blob,
+                compression,
+            )?;
+        }
 
         success!(
             "Repacked '{}' into shallow asset bundle <b>{}</b> <i>({})</i> in {:.2?}",
@@ -282,14 +507,44 @@ fn run(args: Vec<OsString>) -> Result<()> {
         return Ok(());
     }
 
+    if args.output.keep_scene_settings && !has_scenes {
+        warn!("--keep-scene-settings only has an effect for --mode scene and hybrid");
+    }
+    if args.output.dedupe_objects && !matches!(args.output.mode, Mode::Asset | Mode::Hybrid) {
+        warn!("--dedupe-objects only has an effect for --mode asset and hybrid");
+    }
+
+    let size_report = args.output.size_report || args.output.size_report_json.is_some();
+    let budget = Budget {
+        max_object_bytes: args.output.budget.max_object_size,
+        max_root_bytes: args.output.budget.max_root_size,
+        max_class_bytes: args.output.budget.max_class_size,
+        max_objects: args.output.budget.max_objects,
+        max_unattributed_bytes: args.output.budget.max_unattributed_size,
+    };
+    let size_checks = size_report || !budget.is_empty();
+
     let (mut repack_scenes, extra_objects) = unity_scene_repacker::repack_scenes(
         &env,
         repack_settings,
-        matches!(args.output.mode, Mode::Asset),
+        // merging scenes has to renumber PathIds in monobehaviours, and the size report follows them
+        matches!(args.output.mode, Mode::Asset | Mode::Hybrid | Mode::Multi)
+            || !scene_groups.is_empty()
+            || size_checks,
         args.output.disable,
+        match args.output.root_transforms {
+            RootTransforms::Local => unity_scene_repacker::RootTransforms::Local,
+            RootTransforms::World => unity_scene_repacker::RootTransforms::World,
+            RootTransforms::Identity => unity_scene_repacker::RootTransforms::Identity,
+        },
+        args.output.keep_scene_settings && has_scenes,
     )?;
 
-    if let Some(parent) = args.output.output.parent() {
+    let output_dir = match args.output.mode {
+        Mode::Multi => Some(args.output.output.as_path()),
+        _ => args.output.output.parent(),
+    };
+    if let Some(parent) = output_dir {
         DirBuilder::new()
             .recursive(true)
             .create(parent)
@@ -298,11 +553,29 @@ fn run(args: Vec<OsString>) -> Result<()> {
 
     let enable_typetree = false; // TODO make this configurable / infer if necessary
 
-    let new_size = match args.output.mode {
+    let pack_settings = PackSettings {
+        size_report,
+        budget,
+        scene_groups,
+        root_container: args.output.root_container.clone().map(|name| match name {
+            Some(name) => RootContainer::Named(name),
+            None => RootContainer::SceneName,
+        }),
+        path_ids: match args.output.stable_path_ids {
+            true => PathIdStrategy::Stable,
+            false => PathIdStrategy::Sequential,
+        },
+        dedupe_objects: args.output.dedupe_objects,
+    };
+
+    // files with 64-bit PathIds can only be packed without being merged into another file
+    let has_big_ids = repack_scenes
+        .iter()
+        .any(|scene| matches!(scene.serialized.m_bigIDEnabled, Some(enabled) if enabled != 0));
+
+    let (new_size, checksum) = match args.output.mode {
         Mode::Scene => {
-            let mut out = BufWriter::new(
-                File::create(&args.output.output).context("Could not write to output file")?,
-            );
+            let mut out = Cursor::new(Vec::new());
 
             let stats = unity_scene_repacker::pack_to_scene_bundle(
                 &mut out,
@@ -311,18 +584,19 @@ fn run(args: Vec<OsString>) -> Result<()> {
                 &env.tpk,
                 &unity_version,
                 repack_scenes.as_mut_slice(),
+                extra_objects,
                 compression,
-            )
-            .context("trying to repack bundle")?;
+                &pack_settings,
+            );
+            let stats = hint_big_ids(stats, has_big_ids).context("trying to repack bundle")?;
 
             print_stats(&stats, args.repack.scene_objects.is_some());
+            output_size_report(&stats, &args.output)?;
 
-            out.get_ref().metadata()?.len() as usize
+            (write_output(&args.output.output, out)?, stats.checksum)
         }
         Mode::Asset => {
-            let mut out = BufWriter::new(
-                File::create(&args.output.output).context("Could not write to output file")?,
-            );
+            let mut out = Cursor::new(Vec::new());
             let stats = unity_scene_repacker::pack_to_asset_bundle(
                 &env,
                 &mut out,
@@ -332,20 +606,102 @@ fn run(args: Vec<OsString>) -> Result<()> {
                 extra_objects,
                 compression,
                 enable_typetree,
-            )?;
+                &pack_settings,
+            );
+            let stats = hint_big_ids(stats, has_big_ids)?;
             print_stats(&stats, args.repack.scene_objects.is_some());
+            output_size_report(&stats, &args.output)?;
 
-            out.get_ref().metadata()?.len() as usize
+            (write_output(&args.output.output, out)?, stats.checksum)
+        }
+        Mode::Hybrid => {
+            let mut out = Cursor::new(Vec::new());
+
+            let stats = unity_scene_repacker::pack_to_hybrid_bundle(
+                &mut out,
+                name,
+                &tpk_blob,
+                &env.tpk,
+                &unity_version,
+                repack_scenes.as_mut_slice(),
+                extra_objects,
+                compression,
+                enable_typetree,
+                &pack_settings,
+            );
+            let stats = hint_big_ids(stats, has_big_ids).context("trying to repack bundle")?;
+
+            print_stats(&stats, args.repack.scene_objects.is_some());
+            output_size_report(&stats, &args.output)?;
+
+            (write_output(&args.output.output, out)?, stats.checksum)
+        }
+        Mode::Multi => {
+            let multi = unity_scene_repacker::pack_to_multi_asset_bundle(
+                name,
+                &tpk_blob,
+                &env.tpk,
+                &unity_version,
+                repack_scenes,
+                extra_objects,
+                &split,
+                compression,
+                enable_typetree,
+                &pack_settings,
+            );
+            let multi = hint_big_ids(multi, has_big_ids)?;
+            print_stats(&multi.stats, args.repack.scene_objects.is_some());
+            output_size_report(&multi.stats, &args.output)?;
+
+            let mut total_size = 0;
+            for bundle in multi.bundles.iter().chain([&multi.manifest]) {
+                let path = args.output.output.join(&bundle.name);
+                std::fs::write(&path, &bundle.data)
+                    .with_context(|| format!("Could not write bundle '{}'", path.display()))?;
+                total_size += bundle.data.len();
+
+                match bundle.dependencies.as_slice() {
+                    [] => info!("Wrote <b>{}</b> (CRC {})", bundle.name, bundle.checksum.crc),
+                    dependencies => info!(
+                        "Wrote <b>{}</b> (CRC {}), depending on {}",
+                        bundle.name,
+                        bundle.checksum.crc,
+                        dependencies.join(", ")
+                    ),
+                }
+                if args.output.verify {
+                    inspect::verify_output(&path, &env)?;
+                }
+            }
+
+            (total_size, multi.manifest.checksum)
         }
         Mode::AssetShallow => todo!(),
     };
 
+    if !matches!(args.output.mode, Mode::Multi) {
+        if args.output.verify {
+            inspect::verify_output(&args.output.output, &env)?;
+        }
+        info!("CRC <b>{}</b>, hash {}", checksum.crc, checksum.hash);
+        if let Some(manifest_name) = &args.output.manifest {
+            let outputs = [(args.output.output.clone(), checksum.hash)];
+            write_manifest(&outputs, manifest_name, &env, &tpk_blob, compression)?;
+        }
+    } else if args.output.manifest.is_some() {
+        warn!(
+            "--mode multi always writes a manifest named after --bundle-name, ignoring --manifest"
+        );
+    }
+
     success!(
         "Repacked '{}' into {} <b>{}</b> <i>({})</i> in {:.2?}",
         name,
         match args.output.mode {
             Mode::Scene => "scenebundle",
             Mode::Asset => "assetbundle",
+            Mode::Hybrid => "hybrid bundle",
+            Mode::Multi => "asset bundles in",
             Mode::AssetShallow => todo!(),
         },
         args.output.output.display(),
@@ -356,6 +712,102 @@ fn run(args: Vec<OsString>) -> Result<()> {
     Ok(())
 }
 
+/// Writes the manifest bundle listing the bundles at `outputs` into the directory of the first one
+fn write_manifest(
+    outputs: &[(PathBuf, Hash128)],
+    manifest_name: &str,
+    env: &Environment,
+    tpk_blob: &TpkTypeTreeBlob,
+    compression: CompressionType,
+) -> Result<()> {
+    let manifest_path = outputs[0].0.with_file_name(manifest_name);
+    let entries = outputs
+        .iter()
+        .map(|(output, hash)| {
+            ensure!(
+                manifest_path != *output,
+                "The manifest cannot have the same name as the output"
+            );
+            let bundle_name = output
+                .file_name()
+                .and_then(OsStr::to_str)
+                .context("Output path has no file name")?;
+            Ok(ManifestEntry {
+                name: bundle_name.to_owned(),
+                hash: *hash,
+                dependencies: Vec::new(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut out =
+        BufWriter::new(File::create(&manifest_path).context("Could not write to manifest file")?);
+    manifest::pack_manifest_bundle(
+        &mut out,
+        manifest_name,
+        tpk_blob,
+        &env.tpk,
+        env.unity_version()?,
+        &entries,
+        compression,
+    )?;
+    info!("Wrote manifest <b>{}</b>", manifest_path.display());
+
+    Ok(())
+}
+
+/// Only creates the output file once the bundle is packed, so a failed build leaves an existing
+/// bundle at `path` untouched
+fn write_output(path: &Path, out: Cursor<Vec<u8>>) -> Result<usize> {
+    let data = out.into_inner();
+    std::fs::write(path, &data).context("Could not write to output file")?;
+    Ok(data.len())
+}
+
+/// Points at the options which pack files with 64-bit PathIds, as all others merge them
+fn hint_big_ids<T>(result: Result<T>, has_big_ids: bool) -> Result<T> {
+    match has_big_ids {
+        true => result.context(
+            "Files with 64-bit PathIds can only be packed using --mode scene without --merge-scenes or --scene-groups",
+        ),
+        false => result,
+    }
+}
+
+fn locate_game(game: GameArgs) -> Result<PathBuf> {
+    match game.game_dir {
+        Some(game_dir) => resolve_game_dir(game_dir),
+        None => {
+            let game = game.steam_game.unwrap();
+            locate::locate_steam_game(&game)
+        }
+    }
+}
+
+fn resolve_game_dir(game_dir: PathBuf) -> Result<PathBuf> {
+    ensure!(
+        game_dir.exists(),
+        "Game directory '{}' does not exist",
+        game_dir.display()
+    );
+    Ok(match locate::find_unity_data_dir(&game_dir) {
+        Ok(Some(data_dir)) => data_dir,
+        _ => game_dir,
+    })
+}
+
+fn open_env(game_dir: &Path) -> Result<Environment> {
+    let tpk = TypeTreeCache::new(TpkTypeTreeBlob::embedded());
+    let game_files = GameFiles::probe(game_dir)?;
+    Ok(Environment::new(game_files, tpk))
+}
+
+fn read_scene_objects(path: &Path) -> Result<IndexMap<String, Vec<SceneObject>>> {
+    let preloads = std::fs::read_to_string(path)
+        .with_context(|| format!("couldn't find scene objects json '{}'", path.display()))?;
+    json5::from_str(&preloads).context("error parsing the scene objects json")
+}
+
 fn print_stats(stats: &Stats, has_scene_objects: bool) {
     if has_scene_objects {
         info!(
@@ -367,6 +819,58 @@ fn print_stats(stats: &Stats, has_scene_objects: bool) {
             friendly_size(stats.size_before),
             friendly_size(stats.size_after)
         );
+        if stats.deduplicated_bytes > 0 {
+            info!(
+                "Saved {} by deduplicating identical objects",
+                friendly_size(stats.deduplicated_bytes)
+            );
+        }
+        println!();
+    }
+}
+
+fn output_size_report(stats: &Stats, output: &OutputArgs) -> Result<()> {
+    let Some(report) = &stats.size_report else {
+        return Ok(());
+    };
+
+    if output.size_report {
+        print_size_report(report);
+    }
+    if let Some(path) = &output.size_report_json {
+        std::fs::write(path, serde_json::to_string_pretty(report)?)
+            .with_context(|| format!("Could not write size report to '{}'", path.display()))?;
+        info!("Wrote size report to <b>{}</b>", path.display());
+    }
+    Ok(())
+}
+
+fn print_size_report(report: &SizeReport) {
+    const MAX_ROWS: usize = 20;
+
+    for (title, entries) in [
+        ("root", &report.by_root),
+        ("class", &report.by_class),
+        ("scene", &report.by_scene),
+    ] {
+        info!("<b>Size by {title}</b>");
+        let width = entries
+            .iter()
+            .take(MAX_ROWS)
+            .map(|entry| entry.name.len())
+            .max()
+            .unwrap_or(0);
+        for entry in entries.iter().take(MAX_ROWS) {
+            println!(
+                "  {:<width$}  {:>10}  {:>6} objects",
+                entry.name,
+                friendly_size(entry.bytes),
+                entry.objects,
+            );
+        }
+        if entries.len() > MAX_ROWS {
+            println!("  ... and {} more", entries.len() - MAX_ROWS);
+        }
         println!();
     }
 }
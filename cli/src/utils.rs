@@ -14,3 +14,23 @@ pub fn friendly_size(size: usize) -> String {
         format!("{:.2} {}", size, UNITS[unit])
     }
 }
+
+/// Parses sizes like `500`, `200KiB` or `1.5 MiB`. `KB`/`MB`/`GB` are treated as binary units too.
+pub fn parse_size(input: &str) -> Result<usize, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{input}'"))?;
+    let factor = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        other => return Err(format!("unknown size unit '{other}'")),
+    };
+    Ok((number * factor as f64) as usize)
+}
@@ -0,0 +1,147 @@
+//! Keeping the parents of requested objects as stripped down placeholders.
+use anyhow::Result;
+use indexmap::{IndexMap, IndexSet};
+use log::warn;
+use rabex::files::SerializedFile;
+use rabex::objects::pptr::PathId;
+use rabex_env::Environment;
+use rabex_env::unity::types::Transform;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::objects;
+use crate::scene_objects::SceneObject;
+use crate::scene_tree::{self, SceneTree};
+use crate::unity_types::GameObject;
+
+pub(crate) struct KeptAncestors {
+    /// The paths to prune with, where objects which keep their ancestors are replaced by their
+    /// topmost ancestor
+    pub paths: IndexSet<String>,
+    /// The requested paths below each of those topmost ancestors
+    pub requested: FxHashMap<String, Vec<String>>,
+}
+
+/// Names the roots after the requested objects instead of their topmost ancestor, so they can be
+/// found under the path they were requested with. A root containing several requested objects is
+/// listed once for each of them.
+pub(crate) fn rename_roots(
+    requested: &FxHashMap<String, Vec<String>>,
+    roots: &mut Vec<(String, Transform)>,
+) {
+    if requested.is_empty() {
+        return;
+    }
+    *roots = std::mem::take(roots)
+        .into_iter()
+        .flat_map(|(path, transform)| match requested.get(&path) {
+            Some(requested) => requested
+                .iter()
+                .map(|requested| (requested.clone(), transform.clone()))
+                .collect(),
+            None => vec![(path, transform)],
+        })
+        .collect();
+}
+
+/// Rewrites the ancestors of objects with `keep_ancestors` to only contain the path down to the
+/// requested objects and the whitelisted components, storing them in `patches`.
+pub(crate) fn keep_ancestors(
+    env: &Environment,
+    file: &SerializedFile,
+    data: &[u8],
+    tree: &SceneTree,
+    scene_objects: &IndexMap<&str, &SceneObject>,
+    patches: &mut FxHashMap<PathId, Vec<u8>>,
+) -> Result<KeptAncestors> {
+    let mut paths = IndexSet::new();
+    let mut requested: FxHashMap<String, Vec<String>> = FxHashMap::default();
+
+    let complete = scene_objects
+        .values()
+        .filter(|object| object.keep_ancestors().is_none())
+        .filter_map(|object| tree.lookup_path(object.path()))
+        .collect::<FxHashSet<_>>();
+
+    let mut kept_children: FxHashMap<PathId, FxHashSet<PathId>> = FxHashMap::default();
+    let mut kept_components: FxHashMap<PathId, FxHashSet<&str>> = FxHashMap::default();
+    let mut top_paths = Vec::new();
+
+    for (&path, object) in scene_objects {
+        let Some(components) = object.keep_ancestors() else {
+            paths.insert(path.to_owned());
+            continue;
+        };
+        let Some(transform) = tree.lookup_path(path) else {
+            // let pruning report the missing object
+            paths.insert(path.to_owned());
+            continue;
+        };
+
+        let mut chain = vec![transform];
+        let mut current = tree.nodes[&transform].father;
+        while let Some(ancestor) = current {
+            chain.push(ancestor);
+            current = tree.nodes.get(&ancestor).and_then(|node| node.father);
+        }
+
+        if chain
+            .iter()
+            .skip(1)
+            .any(|ancestor| complete.contains(ancestor))
+        {
+            warn!("'{path}' is already included together with its parent, ignoring keep_ancestors");
+            continue;
+        }
+
+        for window in chain.windows(2) {
+            let (child, ancestor) = (window[0], window[1]);
+            kept_children.entry(ancestor).or_default().insert(child);
+            kept_components
+                .entry(ancestor)
+                .or_default()
+                .extend(components.iter().map(String::as_str));
+        }
+        top_paths.push((*chain.last().unwrap(), path));
+    }
+
+    let infos = objects::object_infos(file);
+    let component_names = match kept_components.values().all(FxHashSet::is_empty) {
+        true => FxHashMap::default(),
+        false => objects::component_names(env, file, data)?,
+    };
+
+    for (&ancestor, children) in &kept_children {
+        let info = infos[&ancestor];
+        let object_data = objects::current_object_data(data, patches, info);
+        let patch = scene_tree::retain_children(file, info, object_data, &env.tpk, |child| {
+            children.contains(&child)
+        })?;
+        patches.insert(ancestor, patch);
+
+        let whitelist = &kept_components[&ancestor];
+        let go_id = tree.nodes[&ancestor].game_object;
+        let go_info = infos[&go_id];
+        // keep the components already removed by exclusions
+        let go_data = objects::current_object_data(data, patches, go_info);
+        let mut go: GameObject = objects::read_object(file, go_info, go_data, &env.tpk)?;
+        go.m_Component.retain(|pair| {
+            let component = pair.component.m_PathID;
+            component == ancestor
+                || component_names
+                    .get(&component)
+                    .is_some_and(|names| names.iter().any(|name| whitelist.contains(name.as_str())))
+        });
+        patches.insert(go_id, objects::write_object(file, go_info, &go, &env.tpk)?);
+    }
+
+    for (top, path) in top_paths {
+        let top_path = tree.path(top);
+        requested
+            .entry(top_path.clone())
+            .or_default()
+            .push(path.to_owned());
+        paths.insert(top_path);
+    }
+
+    Ok(KeptAncestors { paths, requested })
+}
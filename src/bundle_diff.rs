@@ -0,0 +1,538 @@
+//! Structural comparison of two produced bundles.
+use anyhow::{Context, Result};
+use rabex::files::SerializedFile;
+use rabex::objects::pptr::PathId;
+use rabex::typetree::TypeTreeProvider;
+use rustc_hash::FxHashMap;
+use serde_derive::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+
+use crate::inspect::{self, BundleEntry};
+use crate::objects;
+use crate::scene_tree::SceneTree;
+use crate::unity_types::GameObject;
+
+/// Values with a longer JSON representation are replaced by a short summary
+const MAX_VALUE_LEN: usize = 200;
+
+#[derive(Debug, Default, Serialize)]
+pub struct BundleDiff {
+    pub files_added: Vec<String>,
+    pub files_removed: Vec<String>,
+    pub container_added: Vec<String>,
+    pub container_removed: Vec<String>,
+    pub externals: Vec<ExternalsDiff>,
+    pub objects: Vec<ObjectDiff>,
+}
+
+impl BundleDiff {
+    pub fn is_empty(&self) -> bool {
+        self.files_added.is_empty()
+            && self.files_removed.is_empty()
+            && self.container_added.is_empty()
+            && self.container_removed.is_empty()
+            && self.externals.is_empty()
+            && self.objects.is_empty()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExternalsDiff {
+    pub file: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ObjectDiff {
+    pub file: String,
+    /// What the object was matched by, see [`ObjectKey`]
+    pub object: String,
+    /// The PathId in the new bundle, or in the old one for removed objects
+    pub path_id: PathId,
+    pub class: String,
+    #[serde(flatten)]
+    pub change: ObjectChange,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum ObjectChange {
+    Added,
+    Removed,
+    /// The serialized bytes differ
+    Modified {
+        fields: Vec<FieldDiff>,
+    },
+    /// The serialized bytes differ, but the object could not be decoded to compare its fields,
+    /// e.g. a `MonoBehaviour` in a bundle without typetrees
+    DataChanged,
+}
+
+/// Identifies an object independently of its PathId, so that bundles where the objects were
+/// numbered differently can be compared
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ObjectKey {
+    /// A `GameObject` or one of its components, by the path of the `GameObject` and how many
+    /// objects of the same class come before it at that path
+    Hierarchy {
+        path: String,
+        class: String,
+        index: usize,
+    },
+    /// An object outside of the hierarchy, by its name and how many objects of the same class and
+    /// name have a lower PathId
+    Named {
+        class: String,
+        name: String,
+        index: usize,
+    },
+    /// Objects without a name are matched by PathId, which only works if it did not change
+    /// between the builds, e.g. with [`PathIdStrategy::Stable`](crate::PathIdStrategy::Stable)
+    PathId(PathId),
+}
+
+impl Display for ObjectKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjectKey::Hierarchy { path, class, index } => {
+                write!(f, "{path}")?;
+                if class != "GameObject" {
+                    write!(f, " {class}")?;
+                }
+                if *index > 0 {
+                    write!(f, " #{index}")?;
+                }
+                Ok(())
+            }
+            ObjectKey::Named { name, index, .. } => match index {
+                0 => write!(f, "'{name}'"),
+                _ => write!(f, "'{name}' #{index}"),
+            },
+            ObjectKey::PathId(path_id) => write!(f, "{path_id}"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldDiff {
+    pub field: String,
+    pub old: Value,
+    pub new: Value,
+}
+
+pub fn diff_bundles(
+    old: &[BundleEntry],
+    new: &[BundleEntry],
+    tpk: &impl TypeTreeProvider,
+) -> Result<BundleDiff> {
+    let mut diff = BundleDiff::default();
+
+    let old_info = inspect::inspect_bundle(old, tpk)?;
+    let new_info = inspect::inspect_bundle(new, tpk)?;
+    let container_paths = |info: &inspect::BundleInfo| -> Vec<String> {
+        info.asset_bundle
+            .iter()
+            .flat_map(|ab| ab.container.iter().map(|entry| entry.path.clone()))
+            .collect()
+    };
+    let (old_container, new_container) = (container_paths(&old_info), container_paths(&new_info));
+    diff.container_added = difference(&new_container, &old_container);
+    diff.container_removed = difference(&old_container, &new_container);
+
+    let old_names = old.iter().map(|e| e.name.clone()).collect::<Vec<_>>();
+    let new_names = new.iter().map(|e| e.name.clone()).collect::<Vec<_>>();
+    diff.files_added = difference(&new_names, &old_names);
+    diff.files_removed = difference(&old_names, &new_names);
+
+    for old_entry in old {
+        let Some(new_entry) = new.iter().find(|entry| entry.name == old_entry.name) else {
+            continue;
+        };
+        let (Some(_), Some(_)) = (&old_entry.serialized, &new_entry.serialized) else {
+            continue;
+        };
+
+        diff_file(old_entry, new_entry, tpk, &mut diff)
+            .with_context(|| format!("Could not compare '{}'", old_entry.name))?;
+    }
+
+    Ok(diff)
+}
+
+fn diff_file(
+    old_entry: &BundleEntry,
+    new_entry: &BundleEntry,
+    tpk: &impl TypeTreeProvider,
+    diff: &mut BundleDiff,
+) -> Result<()> {
+    let (old, new) = (
+        old_entry.serialized.as_ref().unwrap(),
+        new_entry.serialized.as_ref().unwrap(),
+    );
+
+    let externals = |file: &SerializedFile| -> Vec<String> {
+        file.m_Externals
+            .iter()
+            .map(|external| external.pathName.clone())
+            .collect()
+    };
+    let (old_externals, new_externals) = (externals(old), externals(new));
+    if old_externals != new_externals {
+        diff.externals.push(ExternalsDiff {
+            file: old_entry.name.clone(),
+            added: difference(&new_externals, &old_externals),
+            removed: difference(&old_externals, &new_externals),
+        });
+    }
+
+    let old_objects = objects::object_infos(old);
+    let new_objects = objects::object_infos(new);
+    let old_keys = object_keys(old, &old_entry.data, tpk)?;
+    let new_keys = object_keys(new, &new_entry.data, tpk)?;
+
+    let by_key = |keys: &FxHashMap<PathId, ObjectKey>| -> BTreeMap<ObjectKey, PathId> {
+        keys.iter()
+            .map(|(&path_id, key)| (key.clone(), path_id))
+            .collect()
+    };
+    let (old_by_key, new_by_key) = (by_key(&old_keys), by_key(&new_keys));
+    let all_keys = old_by_key
+        .keys()
+        .chain(new_by_key.keys())
+        .collect::<BTreeSet<_>>();
+
+    for key in all_keys {
+        let old_info = old_by_key.get(key).map(|path_id| old_objects[path_id]);
+        let new_info = new_by_key.get(key).map(|path_id| new_objects[path_id]);
+        let (change, class, path_id) = match (old_info, new_info) {
+            (Some(old_info), None) => (
+                ObjectChange::Removed,
+                objects::class_id(old, old_info),
+                old_info.m_PathID,
+            ),
+            (None, Some(new_info)) => (
+                ObjectChange::Added,
+                objects::class_id(new, new_info),
+                new_info.m_PathID,
+            ),
+            (Some(old_info), Some(new_info)) => {
+                let old_data = objects::object_data(&old_entry.data, old_info);
+                let new_data = objects::object_data(&new_entry.data, new_info);
+                let (old_class, new_class) = (
+                    objects::class_id(old, old_info),
+                    objects::class_id(new, new_info),
+                );
+                if old_data == new_data && old_class == new_class {
+                    continue;
+                }
+
+                let mut fields = Vec::new();
+                let change = if old_class != new_class {
+                    fields.push(FieldDiff {
+                        field: "<class>".to_owned(),
+                        old: Value::String(format!("{old_class:?}")),
+                        new: Value::String(format!("{new_class:?}")),
+                    });
+                    ObjectChange::Modified { fields }
+                } else {
+                    let old_value = objects::read_object::<Value>(old, old_info, old_data, tpk);
+                    let new_value = objects::read_object::<Value>(new, new_info, new_data, tpk);
+                    match (old_value, new_value) {
+                        (Ok(old_value), Ok(new_value)) => {
+                            let pptrs = PPtrKeys {
+                                old: &old_keys,
+                                new: &new_keys,
+                            };
+                            diff_values(
+                                &old_value,
+                                &new_value,
+                                &pptrs,
+                                &mut String::new(),
+                                &mut fields,
+                            );
+                            // only references to renumbered objects changed
+                            if fields.is_empty() {
+                                continue;
+                            }
+                            ObjectChange::Modified { fields }
+                        }
+                        _ => ObjectChange::DataChanged,
+                    }
+                };
+
+                (change, new_class, new_info.m_PathID)
+            }
+            (None, None) => unreachable!(),
+        };
+
+        diff.objects.push(ObjectDiff {
+            file: old_entry.name.clone(),
+            object: key.to_string(),
+            path_id,
+            class: format!("{class:?}"),
+            change,
+        });
+    }
+
+    Ok(())
+}
+
+/// The [`ObjectKey`] of every object in `file`
+fn object_keys(
+    file: &SerializedFile,
+    data: &[u8],
+    tpk: &impl TypeTreeProvider,
+) -> Result<FxHashMap<PathId, ObjectKey>> {
+    let infos = objects::object_infos(file);
+    let tree = SceneTree::build(file, data, tpk)?;
+
+    let mut keys = FxHashMap::default();
+    let mut counts = FxHashMap::<(String, String), usize>::default();
+    let mut add_key = |keys: &mut FxHashMap<_, _>, path_id, path: String, class: String| {
+        let count = counts.entry((path.clone(), class.clone())).or_default();
+        let index = *count;
+        *count += 1;
+        keys.insert(path_id, ObjectKey::Hierarchy { path, class, index });
+    };
+
+    let mut nodes = tree.nodes.values().collect::<Vec<_>>();
+    nodes.sort_by_key(|node| node.game_object);
+    for node in nodes {
+        let path = tree.path(node.transform);
+        let go_info = infos[&node.game_object];
+        let go: GameObject =
+            objects::read_object(file, go_info, objects::object_data(data, go_info), tpk)?;
+        add_key(
+            &mut keys,
+            node.game_object,
+            path.clone(),
+            "GameObject".to_owned(),
+        );
+        for pair in go.m_Component {
+            let Some(info) = infos.get(&pair.component.m_PathID) else {
+                continue;
+            };
+            let class = format!("{:?}", objects::class_id(file, info));
+            add_key(&mut keys, info.m_PathID, path.clone(), class);
+        }
+    }
+
+    let mut rest = file
+        .objects()
+        .filter(|info| !keys.contains_key(&info.m_PathID))
+        .collect::<Vec<_>>();
+    rest.sort_by_key(|info| info.m_PathID);
+    let mut counts = FxHashMap::<(String, String), usize>::default();
+    for info in rest {
+        let named =
+            objects::read_object::<NamedObject>(file, info, objects::object_data(data, info), tpk);
+        let key = match named {
+            Ok(NamedObject { m_Name }) if !m_Name.is_empty() => {
+                let class = format!("{:?}", objects::class_id(file, info));
+                let count = counts.entry((class.clone(), m_Name.clone())).or_default();
+                let index = *count;
+                *count += 1;
+                ObjectKey::Named {
+                    class,
+                    name: m_Name,
+                    index,
+                }
+            }
+            _ => ObjectKey::PathId(info.m_PathID),
+        };
+        keys.insert(info.m_PathID, key);
+    }
+
+    Ok(keys)
+}
+
+/// Keys of the objects in both files, to compare references by what they point to
+struct PPtrKeys<'a> {
+    old: &'a FxHashMap<PathId, ObjectKey>,
+    new: &'a FxHashMap<PathId, ObjectKey>,
+}
+
+impl PPtrKeys<'_> {
+    /// Whether both values are references to the same object, or the targets as the values to
+    /// show if they are references to different ones
+    fn compare(&self, old: &Value, new: &Value) -> Option<Result<(), (Value, Value)>> {
+        let local_path_id = |value: &Value| match (value.get("m_FileID"), value.get("m_PathID")) {
+            (Some(file_id), Some(path_id)) if file_id == 0 => path_id.as_i64(),
+            _ => None,
+        };
+        let (old_path_id, new_path_id) = (local_path_id(old)?, local_path_id(new)?);
+        let (old_key, new_key) = (self.old.get(&old_path_id), self.new.get(&new_path_id));
+        if (old_path_id == 0 && new_path_id == 0) || (old_key.is_some() && old_key == new_key) {
+            return Some(Ok(()));
+        }
+        let show = |key: Option<&ObjectKey>, value: &Value| {
+            key.map_or_else(|| value.clone(), |key| Value::String(key.to_string()))
+        };
+        Some(Err((show(old_key, old), show(new_key, new))))
+    }
+}
+
+fn diff_values(
+    old: &Value,
+    new: &Value,
+    pptrs: &PPtrKeys,
+    field: &mut String,
+    out: &mut Vec<FieldDiff>,
+) {
+    if let Some(result) = pptrs.compare(old, new) {
+        if let Err((old, new)) = result {
+            out.push(FieldDiff {
+                field: field.clone(),
+                old,
+                new,
+            });
+        }
+        return;
+    }
+
+    match (old, new) {
+        (Value::Object(old_map), Value::Object(new_map)) => {
+            let keys = old_map
+                .keys()
+                .chain(new_map.keys().filter(|key| !old_map.contains_key(*key)));
+            for key in keys {
+                let len = field.len();
+                if !field.is_empty() {
+                    field.push('.');
+                }
+                field.push_str(key);
+                match (old_map.get(key), new_map.get(key)) {
+                    (Some(old), Some(new)) => diff_values(old, new, pptrs, field, out),
+                    (old, new) => out.push(FieldDiff {
+                        field: field.clone(),
+                        old: old.map_or(Value::Null, summarize),
+                        new: new.map_or(Value::Null, summarize),
+                    }),
+                }
+                field.truncate(len);
+            }
+        }
+        (Value::Array(old_items), Value::Array(new_items))
+            if old_items.len() == new_items.len() =>
+        {
+            for (i, (old, new)) in old_items.iter().zip(new_items).enumerate() {
+                let len = field.len();
+                field.push_str(&format!("[{i}]"));
+                diff_values(old, new, pptrs, field, out);
+                field.truncate(len);
+            }
+        }
+        (old, new) if old != new => out.push(FieldDiff {
+            field: field.clone(),
+            old: summarize(old),
+            new: summarize(new),
+        }),
+        _ => {}
+    }
+}
+
+fn summarize(value: &Value) -> Value {
+    let json = value.to_string();
+    if json.len() <= MAX_VALUE_LEN {
+        return value.clone();
+    }
+    match value {
+        Value::Array(items) => Value::String(format!("<array of {} elements>", items.len())),
+        Value::Object(map) => Value::String(format!("<object with {} fields>", map.len())),
+        _ => {
+            let mut end = MAX_VALUE_LEN;
+            while !json.is_char_boundary(end) {
+                end -= 1;
+            }
+            Value::String(format!("{}...", &json[..end]))
+        }
+    }
+}
+
+fn difference(a: &[String], b: &[String]) -> Vec<String> {
+    let b = b.iter().collect::<BTreeSet<_>>();
+    a.iter().filter(|item| !b.contains(item)).cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{self, TestScene, Tpk};
+
+    fn bundle(scene: TestScene, tpk: &Tpk) -> Vec<BundleEntry> {
+        let scene = scene.repack(tpk);
+        vec![BundleEntry {
+            name: "CAB-test".to_owned(),
+            data: scene.serialized_data.as_ref().to_vec(),
+            serialized: Some(scene.serialized),
+        }]
+    }
+
+    fn changes(diff: &BundleDiff) -> Vec<(&str, &str)> {
+        diff.objects
+            .iter()
+            .map(|object| {
+                let change = match object.change {
+                    ObjectChange::Added => "added",
+                    ObjectChange::Removed => "removed",
+                    ObjectChange::Modified { .. } => "modified",
+                    ObjectChange::DataChanged => "data changed",
+                };
+                (object.object.as_str(), change)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn renumbered_objects_are_matched() {
+        let tpk = test_utils::tpk();
+
+        let mut old = TestScene::new("scene");
+        let material = old.add_material(&tpk, "Material");
+        old.add_renderer(&tpk, "A", material);
+
+        // the same objects, added in a different order
+        let mut new = TestScene::new("scene");
+        new.add_renderer(&tpk, "A", 4);
+        assert_eq!(new.add_material(&tpk, "Material"), 4);
+        new.add_renderer(&tpk, "B", 4);
+
+        let diff = diff_bundles(&bundle(old, &tpk), &bundle(new, &tpk), &tpk).unwrap();
+        assert_eq!(
+            changes(&diff),
+            [
+                ("B", "added"),
+                ("B MeshRenderer", "added"),
+                ("B Transform", "added"),
+            ]
+        );
+    }
+
+    #[test]
+    fn changed_references_show_their_target() {
+        let tpk = test_utils::tpk();
+
+        let scene = |used: usize| {
+            let mut scene = TestScene::new("scene");
+            let materials = [
+                scene.add_material(&tpk, "Red"),
+                scene.add_material(&tpk, "Blue"),
+            ];
+            scene.add_renderer(&tpk, "A", materials[used]);
+            bundle(scene, &tpk)
+        };
+
+        let diff = diff_bundles(&scene(0), &scene(1), &tpk).unwrap();
+        assert_eq!(changes(&diff), [("A MeshRenderer", "modified")]);
+        let ObjectChange::Modified { fields } = &diff.objects[0].change else {
+            unreachable!()
+        };
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].field, "m_Materials[0]");
+        assert_eq!(fields[0].old, "'Red'");
+        assert_eq!(fields[0].new, "'Blue'");
+    }
+}
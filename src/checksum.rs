@@ -0,0 +1,150 @@
+//! Checksums of the uncompressed content of a bundle.
+use anyhow::Result;
+use md4::{Digest, Md4};
+use rabex::UnityVersion;
+use rabex::files::bundlefile::{BundleFileBuilder, CompressionType};
+use std::io::{Cursor, Seek, Write};
+
+use crate::unity_types::Hash128;
+
+/// `crc` is what `AssetBundle.LoadFromFile(path, crc)` checks against, `hash` can be used to
+/// detect changed bundles, e.g. in the `AssetBundleManifest`.
+///
+/// Both only depend on the uncompressed content, so they do not change with the compression.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BundleChecksum {
+    pub crc: u32,
+    pub hash: Hash128,
+}
+
+/// Computes a [`BundleChecksum`] over data passed in one piece at a time
+#[derive(Default)]
+struct ChecksumHasher {
+    crc: crc32fast::Hasher,
+    md4: Md4,
+}
+
+impl ChecksumHasher {
+    fn update(&mut self, data: &[u8]) {
+        self.crc.update(data);
+        self.md4.update(data);
+    }
+
+    fn finish(self) -> BundleChecksum {
+        BundleChecksum {
+            crc: self.crc.finalize(),
+            hash: Hash128(self.md4.finalize().into()),
+        }
+    }
+}
+
+/// Writes `files` into a UnityFS bundle, and computes the [`BundleChecksum`] over their data.
+///
+/// The uncompressed block data of the bundle is the files one after another, so this is the same
+/// as checksumming the block data.
+pub(crate) fn write_unityfs(
+    writer: impl Write + Seek,
+    unity_version: &UnityVersion,
+    files: Vec<(String, Vec<u8>)>,
+    compression: CompressionType,
+) -> Result<BundleChecksum> {
+    let mut hasher = ChecksumHasher::default();
+    let mut builder = BundleFileBuilder::unityfs(7, unity_version);
+    for (name, data) in files {
+        hasher.update(&data);
+        builder.add_file(&name, Cursor::new(data))?;
+    }
+    builder.write(writer, compression)?;
+
+    Ok(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inspect::read_bundle;
+
+    fn checksum(parts: &[&[u8]]) -> BundleChecksum {
+        let mut hasher = ChecksumHasher::default();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finish()
+    }
+
+    /// The check value of CRC-32/ISO-HDLC and the MD4 test suite of RFC 1320
+    #[test]
+    fn published_check_values() {
+        assert_eq!(checksum(&[b"123456789"]).crc, 0xCBF43926);
+        assert_eq!(checksum(&[b"1234", b"", b"56789"]).crc, 0xCBF43926);
+
+        assert_eq!(
+            checksum(&[b"abc"]).hash.to_string(),
+            "a448017aaf21d8525fc10ae87aa6729d"
+        );
+        assert_eq!(
+            checksum(&[b"message ", b"digest"]).hash.to_string(),
+            "d9130a8164549fe818874806e1c7014b"
+        );
+    }
+
+    /// Checks the CRC against one computed by Unity, given a bundle built by Unity and the
+    /// `.manifest` written next to it:
+    ///
+    /// `UNITY_BUNDLE_FIXTURE=path/to/bundle cargo test -- --ignored unity_crc`
+    #[test]
+    #[ignore = "needs a bundle built by Unity"]
+    fn unity_crc() {
+        let path = std::env::var_os("UNITY_BUNDLE_FIXTURE").expect("UNITY_BUNDLE_FIXTURE not set");
+        let path = std::path::PathBuf::from(path);
+        let data = std::fs::read(&path).unwrap();
+        let manifest = std::fs::read_to_string(path.with_extension("manifest"))
+            .or_else(|_| std::fs::read_to_string(format!("{}.manifest", path.display())))
+            .unwrap();
+        let expected = manifest
+            .lines()
+            .find_map(|line| line.strip_prefix("CRC: "))
+            .expect("no CRC in manifest")
+            .trim()
+            .parse::<u32>()
+            .unwrap();
+
+        let entries = read_bundle(&data).unwrap();
+        let files = entries
+            .iter()
+            .map(|entry| entry.data.as_slice())
+            .collect::<Vec<_>>();
+        assert_eq!(checksum(&files).crc, expected);
+    }
+
+    #[test]
+    fn checksum_does_not_depend_on_compression() {
+        let unity_version = crate::test_utils::unity_version();
+        let files: [(&str, &[u8]); 2] = [
+            ("CAB-test.resS", &[1, 2, 3, 4, 5, 6, 7]),
+            ("CAB-test.resource", &[0xAA; 100]),
+        ];
+        let write = |compression| {
+            let files = files
+                .iter()
+                .map(|&(name, data)| (name.to_owned(), data.to_vec()))
+                .collect();
+            let mut out = Cursor::new(Vec::new());
+            let checksum = write_unityfs(&mut out, &unity_version, files, compression).unwrap();
+            (out.into_inner(), checksum)
+        };
+
+        let (uncompressed, checksum) = write(CompressionType::None);
+        let (compressed, compressed_checksum) = write(CompressionType::Lz4hc);
+        assert_eq!(checksum, compressed_checksum);
+
+        for bundle in [uncompressed, compressed] {
+            let entries = read_bundle(&bundle).unwrap();
+            assert_eq!(entries.len(), files.len());
+            for (entry, (name, data)) in entries.iter().zip(files) {
+                assert_eq!(entry.name, name);
+                assert_eq!(entry.data, data);
+            }
+        }
+    }
+}
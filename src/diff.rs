@@ -0,0 +1,346 @@
+//! Compare the requested objects between two versions of a game.
+use anyhow::{Context, Result};
+use indexmap::IndexMap;
+use log::warn;
+use rabex::files::SerializedFile;
+use rabex::files::serializedfile::ObjectInfo;
+use rabex::objects::ClassId;
+use rabex::objects::pptr::PathId;
+use rabex_env::Environment;
+use rabex_env::resolver::EnvResolver as _;
+use rabex_env::unity::types::MonoBehaviour;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use rustc_hash::FxHashMap;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+use crate::objects;
+use crate::scene_objects::SceneObject;
+use crate::scene_tree::SceneTree;
+use crate::unity_types::GameObject;
+
+#[derive(Debug, Serialize)]
+pub struct ObjectDiff {
+    pub scene: String,
+    pub path: String,
+    #[serde(flatten)]
+    pub status: ObjectStatus,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ObjectStatus {
+    Unchanged,
+    /// The path does not exist in the old game version, so there is nothing to compare against
+    MissingInOld,
+    /// The path does not exist in the new version, and no object of the same name was found
+    Missing,
+    /// The path does not exist in the new version, but objects of the same name exist at paths that are new to the scene
+    Moved {
+        new_paths: Vec<String>,
+    },
+    /// The object still exists, but its components changed. A component type that was added or
+    /// removed several times is listed once per instance
+    ChangedComponents {
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+}
+
+struct LoadedScene {
+    file: SerializedFile,
+    data: rabex_env::env::Data,
+    infos: FxHashMap<PathId, ObjectInfo>,
+    tree: SceneTree,
+}
+
+pub fn diff_scene_objects(
+    old: &Environment,
+    new: &Environment,
+    scene_objects: &IndexMap<String, Vec<SceneObject>>,
+) -> Result<Vec<ObjectDiff>> {
+    let scenes = scene_objects.iter().collect::<Vec<_>>();
+    let diffs = scenes
+        .par_iter()
+        .map(|&(scene_name, objects)| -> Result<_> {
+            let old_scene = load_scene(old, scene_name).context("In old game version")?;
+            let new_scene = load_scene(new, scene_name).context("In new game version")?;
+
+            objects
+                .iter()
+                .filter(|object| !object.is_whole_scene())
+                .map(|object| {
+                    let path = object.path();
+                    let status =
+                        diff_object(old, new, old_scene.as_ref(), new_scene.as_ref(), path)
+                            .with_context(|| {
+                                format!("Could not compare '{path}' in {scene_name}")
+                            })?;
+                    Ok(ObjectDiff {
+                        scene: scene_name.clone(),
+                        path: path.to_owned(),
+                        status,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(diffs.into_iter().flatten().collect())
+}
+
+/// Rewrites the scene objects to the paths they have in the new game version.
+///
+/// Objects that moved to exactly one new location get their new path, missing objects are dropped.
+pub fn migrate_scene_objects(
+    scene_objects: &IndexMap<String, Vec<SceneObject>>,
+    diffs: &[ObjectDiff],
+) -> IndexMap<String, Vec<SceneObject>> {
+    let mut migrated: IndexMap<String, Vec<SceneObject>> = IndexMap::new();
+    for (scene_name, objects) in scene_objects {
+        let entry = migrated.entry(scene_name.clone()).or_default();
+        for object in objects {
+            let path = object.path();
+            let diff = diffs
+                .iter()
+                .find(|diff| diff.scene == *scene_name && diff.path == *path);
+            match diff.map(|diff| &diff.status) {
+                Some(ObjectStatus::Missing) => {
+                    warn!("Dropping '{path}' in {scene_name}, it does not exist anymore");
+                }
+                Some(ObjectStatus::Moved { new_paths }) if new_paths.len() == 1 => {
+                    entry.push(object.with_path(new_paths[0].clone()));
+                }
+                Some(ObjectStatus::Moved { new_paths }) => {
+                    warn!(
+                        "'{path}' in {scene_name} is ambiguous, candidates are {new_paths:?}. Keeping the old path"
+                    );
+                    entry.push(object.clone());
+                }
+                _ => entry.push(object.clone()),
+            }
+        }
+    }
+    migrated.retain(|_, paths| !paths.is_empty());
+    migrated
+}
+
+fn load_scene(env: &Environment, scene_name: &str) -> Result<Option<LoadedScene>> {
+    let scene_lookup = env.build_settings()?.scene_name_lookup();
+    let Some(scene_index) = scene_lookup.get(scene_name) else {
+        return Ok(None);
+    };
+
+    let (file, data) = env.load_serialized_uncached(format!("level{scene_index}"))?;
+    let tree = SceneTree::build(&file, data.as_ref(), &env.tpk)?;
+    let infos = file
+        .objects()
+        .map(|info| (info.m_PathID, info.clone()))
+        .collect();
+    Ok(Some(LoadedScene {
+        file,
+        data,
+        infos,
+        tree,
+    }))
+}
+
+/// Where a requested path of the old game version is found in the new one
+#[derive(Debug, PartialEq, Eq)]
+enum PathStatus {
+    MissingInOld,
+    Missing,
+    Moved(Vec<String>),
+    /// The `Transform`s of the path in the old and new version
+    Found(PathId, PathId),
+}
+
+/// Looks up `path` in both versions. When it is gone from the new one, objects of the same name
+/// at paths which did not exist in the old version are the candidates it moved to.
+fn locate_path(old: Option<&SceneTree>, new: Option<&SceneTree>, path: &str) -> PathStatus {
+    let Some((old, old_transform)) = old.and_then(|old| Some((old, old.lookup_path(path)?))) else {
+        return PathStatus::MissingInOld;
+    };
+    let Some(new) = new else {
+        return PathStatus::Missing;
+    };
+
+    let Some(new_transform) = new.lookup_path(path) else {
+        let name = &old.nodes[&old_transform].name;
+        let mut new_paths = new
+            .find_by_name(name)
+            .map(|node| new.path(node.transform))
+            .filter(|new_path| old.lookup_path(new_path).is_none())
+            .collect::<Vec<_>>();
+        new_paths.sort();
+
+        return match new_paths.is_empty() {
+            true => PathStatus::Missing,
+            false => PathStatus::Moved(new_paths),
+        };
+    };
+
+    PathStatus::Found(old_transform, new_transform)
+}
+
+fn diff_object(
+    old_env: &Environment,
+    new_env: &Environment,
+    old: Option<&LoadedScene>,
+    new: Option<&LoadedScene>,
+    path: &str,
+) -> Result<ObjectStatus> {
+    let (old, new, old_transform, new_transform) =
+        match locate_path(old.map(|old| &old.tree), new.map(|new| &new.tree), path) {
+            PathStatus::MissingInOld => return Ok(ObjectStatus::MissingInOld),
+            PathStatus::Missing => return Ok(ObjectStatus::Missing),
+            PathStatus::Moved(new_paths) => return Ok(ObjectStatus::Moved { new_paths }),
+            PathStatus::Found(old_transform, new_transform) => {
+                (old.unwrap(), new.unwrap(), old_transform, new_transform)
+            }
+        };
+
+    let old_components = components(old_env, old, old.tree.nodes[&old_transform].game_object)?;
+    let new_components = components(new_env, new, new.tree.nodes[&new_transform].game_object)?;
+
+    if old_components == new_components {
+        return Ok(ObjectStatus::Unchanged);
+    }
+    Ok(ObjectStatus::ChangedComponents {
+        added: components_difference(&new_components, &old_components),
+        removed: components_difference(&old_components, &new_components),
+    })
+}
+
+/// Components that occur more often in `a` than in `b`, repeated by the difference in count
+fn components_difference(a: &BTreeMap<String, usize>, b: &BTreeMap<String, usize>) -> Vec<String> {
+    a.iter()
+        .flat_map(|(name, &count)| {
+            let extra = count.saturating_sub(b.get(name).copied().unwrap_or(0));
+            std::iter::repeat_n(name.clone(), extra)
+        })
+        .collect()
+}
+
+/// Class names of the components of the game object, or script names for `MonoBehaviour`s,
+/// with how often they occur
+fn components(
+    env: &Environment,
+    scene: &LoadedScene,
+    game_object: PathId,
+) -> Result<BTreeMap<String, usize>> {
+    let file = &scene.file;
+    let data = scene.data.as_ref();
+    let infos = &scene.infos;
+
+    let go_info = &infos[&game_object];
+    let go: GameObject =
+        objects::read_object(file, go_info, objects::object_data(data, go_info), &env.tpk)?;
+
+    let mut components = BTreeMap::new();
+    for pair in go.m_Component {
+        let Some(info) = infos.get(&pair.component.m_PathID) else {
+            continue;
+        };
+        let class_id = objects::class_id(file, info);
+        if class_id != ClassId::MonoBehaviour {
+            *components.entry(format!("{class_id:?}")).or_default() += 1;
+            continue;
+        }
+
+        let mb: MonoBehaviour =
+            objects::read_object(file, info, objects::object_data(data, info), &env.tpk)?;
+        let name = match mb.m_Script.is_null() {
+            true => "MonoBehaviour".to_owned(),
+            false => {
+                let script = env.deref_read(mb.m_Script, file, &mut Cursor::new(data))?;
+                script.full_name().into_owned()
+            }
+        };
+        *components.entry(name).or_default() += 1;
+    }
+
+    Ok(components)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene_tree::SceneNode;
+
+    /// A tree with a `Transform` for every segment of `paths`
+    fn tree(paths: &[&str]) -> SceneTree {
+        let mut tree = SceneTree {
+            nodes: FxHashMap::default(),
+            roots: Vec::new(),
+        };
+        for path in paths {
+            let mut father = None;
+            for segment in path.split('/') {
+                let siblings = match father {
+                    Some(father) => &tree.nodes[&father].children,
+                    None => &tree.roots,
+                };
+                let existing = siblings
+                    .iter()
+                    .copied()
+                    .find(|id| tree.nodes[id].name == segment);
+                let transform = existing.unwrap_or_else(|| {
+                    let transform = tree.nodes.len() as PathId * 2 + 1;
+                    tree.nodes.insert(
+                        transform,
+                        SceneNode {
+                            transform,
+                            game_object: transform + 1,
+                            name: segment.to_owned(),
+                            father,
+                            children: Vec::new(),
+                        },
+                    );
+                    match father {
+                        Some(father) => tree
+                            .nodes
+                            .get_mut(&father)
+                            .unwrap()
+                            .children
+                            .push(transform),
+                        None => tree.roots.push(transform),
+                    }
+                    transform
+                });
+                father = Some(transform);
+            }
+        }
+        tree
+    }
+
+    #[test]
+    fn classify_paths() {
+        let old = tree(&["Boss Arena/Boss", "Lights/Lamp", "Town/Lamp", "Gone"]);
+        let new = tree(&["Boss Arena/Boss", "Town/Lamp", "Town/Shop"]);
+        let locate = |path| locate_path(Some(&old), Some(&new), path);
+
+        let PathStatus::Found(old_transform, new_transform) = locate("Boss Arena/Boss") else {
+            panic!("unchanged path was not found");
+        };
+        assert_eq!(old.path(old_transform), "Boss Arena/Boss");
+        assert_eq!(new.path(new_transform), "Boss Arena/Boss");
+
+        assert_eq!(locate("Town/Shop"), PathStatus::MissingInOld);
+        assert_eq!(locate("Gone"), PathStatus::Missing);
+        // `Town/Lamp` already existed, so it is not where `Lights/Lamp` moved to
+        assert_eq!(locate("Lights/Lamp"), PathStatus::Missing);
+        assert_eq!(locate_path(Some(&old), None, "Gone"), PathStatus::Missing);
+    }
+
+    #[test]
+    fn moved_paths_did_not_exist_before() {
+        let old = tree(&["Boss Arena/Boss", "Arena/Boss"]);
+        let new = tree(&["Arena/Boss", "Arena 2/Boss", "Arena 3/Boss"]);
+        assert_eq!(
+            locate_path(Some(&old), Some(&new), "Boss Arena/Boss"),
+            PathStatus::Moved(vec!["Arena 2/Boss".to_owned(), "Arena 3/Boss".to_owned()])
+        );
+    }
+}
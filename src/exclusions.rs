@@ -0,0 +1,120 @@
+//! Removing subtrees and components from the requested objects before pruning.
+use anyhow::Result;
+use indexmap::IndexMap;
+use log::warn;
+use rabex::files::SerializedFile;
+use rabex::objects::pptr::PathId;
+use rabex_env::Environment;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::objects;
+use crate::scene_objects::SceneObject;
+use crate::scene_tree::{self, SceneTree};
+use crate::unity_types::GameObject;
+
+pub(crate) struct Excluded<'a> {
+    pub path: &'a str,
+    /// Transform of the excluded subtree, or of the object whose components were removed
+    pub transform: PathId,
+    /// Whether the whole subtree was excluded, or only components
+    pub subtree: bool,
+    /// The `GameObject`s of the subtree, or the removed components
+    pub objects: FxHashSet<PathId>,
+}
+
+/// Detaches excluded subtrees from their parents by removing them from `m_Children`, and
+/// removes excluded components from their `GameObject`.
+///
+/// The excluded objects can still be kept if they are referenced from elsewhere, check the
+/// returned objects against the reachable set after pruning.
+pub(crate) fn apply_exclusions<'a>(
+    env: &Environment,
+    file: &SerializedFile,
+    data: &[u8],
+    tree: &SceneTree,
+    exclusions: &IndexMap<&'a str, &SceneObject>,
+    patches: &mut FxHashMap<PathId, Vec<u8>>,
+) -> Result<Vec<Excluded<'a>>> {
+    let infos = objects::object_infos(file);
+    let component_names = match exclusions
+        .values()
+        .any(|exclusion| !exclusion.excluded_components().is_empty())
+    {
+        true => objects::component_names(env, file, data)?,
+        false => FxHashMap::default(),
+    };
+
+    let mut excluded = Vec::new();
+
+    for (&path, exclusion) in exclusions {
+        let Some(transform) = tree.lookup_path(path) else {
+            warn!("Could not find excluded path '{path}'");
+            continue;
+        };
+
+        let components = exclusion.excluded_components();
+        if !components.is_empty() {
+            let go_id = tree.nodes[&transform].game_object;
+            let go_info = infos[&go_id];
+            let mut go: GameObject = objects::read_object(
+                file,
+                go_info,
+                objects::current_object_data(data, patches, go_info),
+                &env.tpk,
+            )?;
+
+            let mut removed = FxHashSet::default();
+            go.m_Component.retain(|pair| {
+                let component = pair.component.m_PathID;
+                let matches = component_names
+                    .get(&component)
+                    .is_some_and(|names| names.iter().any(|name| components.contains(name)));
+                if matches {
+                    removed.insert(component);
+                }
+                !matches
+            });
+            if removed.is_empty() {
+                warn!("'{path}' has none of the excluded components {components:?}");
+            }
+
+            patches.insert(go_id, objects::write_object(file, go_info, &go, &env.tpk)?);
+            excluded.push(Excluded {
+                path,
+                transform,
+                subtree: false,
+                objects: removed,
+            });
+            continue;
+        }
+
+        let mut game_objects = FxHashSet::default();
+        let mut stack = vec![transform];
+        while let Some(current) = stack.pop() {
+            let Some(node) = tree.nodes.get(&current) else {
+                continue;
+            };
+            game_objects.insert(node.game_object);
+            stack.extend(&node.children);
+        }
+        excluded.push(Excluded {
+            path,
+            transform,
+            subtree: true,
+            objects: game_objects,
+        });
+
+        let Some(father) = tree.nodes[&transform].father else {
+            // roots are only included if requested
+            continue;
+        };
+        let info = infos[&father];
+        let object_data = objects::current_object_data(data, patches, info);
+        let patch = scene_tree::retain_children(file, info, object_data, &env.tpk, |child| {
+            child != transform
+        })?;
+        patches.insert(father, patch);
+    }
+
+    Ok(excluded)
+}
@@ -0,0 +1,305 @@
+//! Export of the `PPtr` reference graph of the repacked objects.
+use anyhow::{Context, Result};
+use rabex::objects::pptr::PathId;
+use rabex::typetree::TypeTreeProvider;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+use crate::references::{ManagedTypes, object_references};
+use crate::{RepackScene, objects};
+
+#[derive(Debug)]
+pub struct GraphNode {
+    /// `scene:<scene>:<path id>` for objects in a repacked scene, `file:<path>:<path id>` for
+    /// objects in external files
+    pub id: String,
+    /// Scene name for objects in a repacked scene, or the path of the external file
+    pub cluster: String,
+    pub path_id: PathId,
+    /// Only known for objects inside repacked scenes
+    pub class: Option<String>,
+    pub name: Option<String>,
+    /// Set if the object is the GameObject of a requested path
+    pub root: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct GraphEdge {
+    pub from: String,
+    pub to: String,
+    pub field: String,
+}
+
+#[derive(Debug, Default)]
+pub struct DependencyGraph {
+    pub nodes: BTreeMap<String, GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+/// Builds the reference graph between the objects that were kept by pruning the scenes.
+pub fn dependency_graph(
+    scenes: &[RepackScene],
+    tpk: &impl TypeTreeProvider,
+) -> Result<DependencyGraph> {
+    let mut graph = DependencyGraph::default();
+
+    for scene in scenes {
+        let file = &scene.serialized;
+        let data = scene.serialized_data.as_ref();
+        let managed = ManagedTypes::of(file);
+        let node_id = |path_id: PathId| format!("scene:{}:{path_id}", scene.scene_name);
+
+        let roots = scene
+            .roots
+            .iter()
+            .map(|(path, transform)| (transform.m_GameObject.m_PathID, path.as_str()))
+            .collect::<BTreeMap<_, _>>();
+
+        for info in file.objects() {
+            if !scene.keep_objects.contains(&info.m_PathID) {
+                continue;
+            }
+
+            let object_data = objects::current_object_data(data, &scene.replacements, info);
+            let tt = match scene.monobehaviour_types.get(&info.m_PathID) {
+                Some(&tt) => Cow::Borrowed(tt),
+                None => file.get_typetree_for(info, tpk)?,
+            };
+            let references =
+                object_references(object_data, &tt, file.m_Header.m_Endianess, &managed)
+                    .with_context(|| format!("Could not read object {}", info.m_PathID))
+                    .with_context(|| format!("In {}", scene.scene_name))?;
+
+            let id = node_id(info.m_PathID);
+            graph.nodes.insert(
+                id.clone(),
+                GraphNode {
+                    id: id.clone(),
+                    cluster: scene.scene_name.clone(),
+                    path_id: info.m_PathID,
+                    class: Some(format!("{:?}", objects::class_id(file, info))),
+                    name: objects::object_name(file, info, object_data, &tt),
+                    root: roots.get(&info.m_PathID).map(|&path| path.to_owned()),
+                },
+            );
+
+            for reference in references {
+                let to = match reference.file_id {
+                    0 => node_id(reference.path_id),
+                    file_id => {
+                        let Some(external) = usize::try_from(file_id - 1)
+                            .ok()
+                            .and_then(|index| file.m_Externals.get(index))
+                        else {
+                            continue;
+                        };
+                        let id = format!("file:{}:{}", external.pathName, reference.path_id);
+                        graph.nodes.entry(id.clone()).or_insert_with(|| GraphNode {
+                            id: id.clone(),
+                            cluster: external.pathName.clone(),
+                            path_id: reference.path_id,
+                            class: None,
+                            name: None,
+                            root: None,
+                        });
+                        id
+                    }
+                };
+                graph.edges.push(GraphEdge {
+                    from: id.clone(),
+                    to,
+                    field: reference.field,
+                });
+            }
+        }
+    }
+
+    // drop references to scene objects that were pruned
+    let nodes = &graph.nodes;
+    graph.edges.retain(|edge| nodes.contains_key(&edge.to));
+
+    Ok(graph)
+}
+
+impl GraphNode {
+    fn label(&self) -> String {
+        let mut label = match (&self.class, &self.name) {
+            (Some(class), Some(name)) => format!("{class} '{name}'"),
+            (Some(class), None) => class.clone(),
+            (None, _) => format!("{}", self.path_id),
+        };
+        if let Some(root) = &self.root {
+            label = format!("{root}\n{label}");
+        }
+        label
+    }
+}
+
+impl DependencyGraph {
+    /// Nodes by cluster, keyed by the id prefix so that a scene and a file of the same name stay
+    /// apart
+    fn clusters(&self) -> BTreeMap<&str, (&str, Vec<&GraphNode>)> {
+        let mut clusters: BTreeMap<&str, (&str, Vec<_>)> = BTreeMap::new();
+        for node in self.nodes.values() {
+            let key = node
+                .id
+                .rsplit_once(':')
+                .map_or(node.id.as_str(), |(key, _)| key);
+            clusters
+                .entry(key)
+                .or_insert_with(|| (node.cluster.as_str(), Vec::new()))
+                .1
+                .push(node);
+        }
+        clusters
+    }
+
+    /// Graphviz representation, with one cluster per scene or external file
+    pub fn to_dot(&self) -> String {
+        fn escape(s: &str) -> String {
+            s.replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n")
+        }
+
+        let mut out = String::new();
+        out.push_str("digraph dependencies {\n");
+        out.push_str("  node [shape=box];\n");
+        for (i, (cluster, nodes)) in self.clusters().into_values().enumerate() {
+            let _ = writeln!(out, "  subgraph cluster_{i} {{");
+            let _ = writeln!(out, "    label=\"{}\";", escape(cluster));
+            for node in nodes {
+                let style = match node.root.is_some() {
+                    true => ", style=bold",
+                    false => "",
+                };
+                let _ = writeln!(
+                    out,
+                    "    \"{}\" [label=\"{}\"{style}];",
+                    escape(&node.id),
+                    escape(&node.label())
+                );
+            }
+            out.push_str("  }\n");
+        }
+        for edge in &self.edges {
+            let _ = writeln!(
+                out,
+                "  \"{}\" -> \"{}\" [label=\"{}\"];",
+                escape(&edge.from),
+                escape(&edge.to),
+                escape(&edge.field)
+            );
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// GraphML representation, with clusters as nested graphs
+    pub fn to_graphml(&self) -> String {
+        fn escape(s: &str) -> String {
+            s.replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;")
+        }
+
+        let mut out = String::new();
+        out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+        out.push_str(
+            "  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n",
+        );
+        out.push_str(
+            "  <key id=\"class\" for=\"node\" attr.name=\"class\" attr.type=\"string\"/>\n",
+        );
+        out.push_str("  <key id=\"root\" for=\"node\" attr.name=\"root\" attr.type=\"string\"/>\n");
+        out.push_str(
+            "  <key id=\"field\" for=\"edge\" attr.name=\"field\" attr.type=\"string\"/>\n",
+        );
+        out.push_str("  <graph id=\"dependencies\" edgedefault=\"directed\">\n");
+        for (i, (cluster, nodes)) in self.clusters().into_values().enumerate() {
+            let _ = writeln!(out, "    <node id=\"cluster_{i}\">");
+            let _ = writeln!(out, "      <data key=\"label\">{}</data>", escape(cluster));
+            let _ = writeln!(
+                out,
+                "      <graph id=\"cluster_{i}:\" edgedefault=\"directed\">"
+            );
+            for node in nodes {
+                let _ = writeln!(out, "        <node id=\"{}\">", escape(&node.id));
+                let _ = writeln!(
+                    out,
+                    "          <data key=\"label\">{}</data>",
+                    escape(&node.label())
+                );
+                if let Some(class) = &node.class {
+                    let _ = writeln!(
+                        out,
+                        "          <data key=\"class\">{}</data>",
+                        escape(class)
+                    );
+                }
+                if let Some(root) = &node.root {
+                    let _ = writeln!(out, "          <data key=\"root\">{}</data>", escape(root));
+                }
+                out.push_str("        </node>\n");
+            }
+            out.push_str("      </graph>\n");
+            out.push_str("    </node>\n");
+        }
+        for edge in &self.edges {
+            let _ = writeln!(
+                out,
+                "    <edge source=\"{}\" target=\"{}\"><data key=\"field\">{}</data></edge>",
+                escape(&edge.from),
+                escape(&edge.to),
+                escape(&edge.field)
+            );
+        }
+        out.push_str("  </graph>\n");
+        out.push_str("</graphml>\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{self, TestScene};
+
+    #[test]
+    fn scene_and_file_of_the_same_name_stay_apart() {
+        let tpk = test_utils::tpk();
+        let mut scene = TestScene::new("level");
+        scene.add_external("level");
+        let renderer = scene.add_external_renderer(&tpk, "Root", 1, 1);
+        let scene = scene.repack(&tpk);
+
+        let graph = dependency_graph(&[scene], &tpk).unwrap();
+
+        let local = &graph.nodes["scene:level:1"];
+        assert_eq!(local.class.as_deref(), Some("GameObject"));
+        assert_eq!(local.name.as_deref(), Some("Root"));
+        assert_eq!(local.root.as_deref(), Some("Root"));
+        let external = &graph.nodes["file:level:1"];
+        assert_eq!(external.class, None);
+
+        // the material is found inside the array of the renderer
+        let edges = graph
+            .edges
+            .iter()
+            .filter(|edge| edge.from == format!("scene:level:{renderer}"))
+            .map(|edge| (edge.field.as_str(), edge.to.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            edges,
+            [
+                ("m_GameObject", "scene:level:1"),
+                ("m_Materials[0]", "file:level:1")
+            ]
+        );
+
+        assert_eq!(graph.to_dot().matches("subgraph").count(), 2);
+    }
+}
@@ -0,0 +1,238 @@
+//! Export of the full GameObject hierarchy of a scene, including component data.
+use anyhow::{Context, Result};
+use log::warn;
+use rabex::files::SerializedFile;
+use rabex::files::serializedfile::ObjectInfo;
+use rabex::objects::ClassId;
+use rabex::objects::pptr::PathId;
+use rabex::typetree::TypeTreeNode;
+use rabex_env::Environment;
+use rabex_env::handle::SerializedFileHandle;
+use rabex_env::unity::types::MonoBehaviour;
+use rustc_hash::FxHashMap;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::Cursor;
+
+use crate::objects;
+use crate::scene_tree::SceneTree;
+use crate::unity_types::{GameObject, Transform};
+
+#[derive(Debug, Serialize)]
+pub struct HierarchyNode {
+    pub name: String,
+    pub path_id: PathId,
+    pub active: bool,
+    pub layer: u32,
+    pub tag: String,
+    pub transform: TransformData,
+    pub components: Vec<ComponentData>,
+    pub children: Vec<HierarchyNode>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransformData {
+    pub position: [f32; 3],
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+#[derive(Debug, Serialize)]
+pub struct ComponentData {
+    pub path_id: PathId,
+    pub class: String,
+    /// Full name of the script, for `MonoBehaviour`s
+    pub script: Option<String>,
+    /// Typetree-decoded fields of the component
+    pub fields: Option<Value>,
+}
+
+/// Exports the hierarchy of `scene_name`, or only the subtree at `root` if specified.
+///
+/// When `component_fields` is set, every component is decoded using its typetree. For
+/// `MonoBehaviour`s, the typetree is generated from the game's assemblies.
+pub fn export_hierarchy(
+    env: &Environment,
+    scene_name: &str,
+    root: Option<&str>,
+    component_fields: bool,
+) -> Result<Vec<HierarchyNode>> {
+    let scene_lookup = env.build_settings()?.scene_name_lookup();
+    let scene_index = scene_lookup
+        .get(scene_name)
+        .with_context(|| format!("Scene '{scene_name}' was not found in game files"))?;
+
+    let (file, data) = env.load_serialized_uncached(format!("level{scene_index}"))?;
+    let data = data.as_ref();
+    let tree = SceneTree::build(&file, data, &env.tpk)?;
+
+    let handle = SerializedFileHandle::new(env, &file, data);
+    let mut scripts = FxHashMap::default();
+    for mb_obj in handle.objects_of::<MonoBehaviour>() {
+        if let Some(script) = mb_obj.mono_script()? {
+            scripts.insert(mb_obj.path_id(), script.full_name().into_owned());
+        }
+    }
+
+    let monobehaviour_types = match component_fields {
+        true => crate::prepare_monobehaviour_types(env, &file, &mut Cursor::new(data)),
+        false => FxHashMap::default(),
+    };
+
+    let exporter = Exporter {
+        env,
+        file: &file,
+        data,
+        tree: &tree,
+        infos: objects::object_infos(&file),
+        scripts,
+        monobehaviour_types,
+        custom_tags: custom_tags(env),
+        component_fields,
+    };
+
+    let roots = match root {
+        Some(path) => vec![
+            tree.lookup_path(path)
+                .with_context(|| format!("Could not find path '{path}' in {scene_name}"))?,
+        ],
+        None => tree.roots.clone(),
+    };
+    roots
+        .into_iter()
+        .map(|transform| exporter.export_node(transform))
+        .collect()
+}
+
+struct Exporter<'a> {
+    env: &'a Environment,
+    file: &'a SerializedFile,
+    data: &'a [u8],
+    tree: &'a SceneTree,
+    infos: FxHashMap<PathId, &'a ObjectInfo>,
+    scripts: FxHashMap<PathId, String>,
+    monobehaviour_types: FxHashMap<PathId, &'a TypeTreeNode>,
+    custom_tags: Vec<String>,
+    component_fields: bool,
+}
+
+impl Exporter<'_> {
+    fn export_node(&self, transform_id: PathId) -> Result<HierarchyNode> {
+        let node = &self.tree.nodes[&transform_id];
+
+        let transform_info = self.infos[&transform_id];
+        let transform: Transform = objects::read_object(
+            self.file,
+            transform_info,
+            objects::object_data(self.data, transform_info),
+            &self.env.tpk,
+        )?;
+        let go_info = self.infos[&node.game_object];
+        let go: GameObject = objects::read_object(
+            self.file,
+            go_info,
+            objects::object_data(self.data, go_info),
+            &self.env.tpk,
+        )?;
+
+        let components = go
+            .m_Component
+            .iter()
+            .filter_map(|pair| self.infos.get(&pair.component.m_PathID))
+            .map(|info| self.export_component(info))
+            .collect::<Result<Vec<_>>>()?;
+
+        let children = node
+            .children
+            .iter()
+            .filter(|child| self.tree.nodes.contains_key(child))
+            .map(|&child| self.export_node(child))
+            .collect::<Result<Vec<_>>>()?;
+
+        let (position, rotation, scale) = (
+            transform.m_LocalPosition,
+            transform.m_LocalRotation,
+            transform.m_LocalScale,
+        );
+        Ok(HierarchyNode {
+            name: go.m_Name,
+            path_id: node.game_object,
+            active: go.m_IsActive,
+            layer: go.m_Layer,
+            tag: tag_name(go.m_Tag, &self.custom_tags),
+            transform: TransformData {
+                position: [position.x, position.y, position.z],
+                rotation: [rotation.x, rotation.y, rotation.z, rotation.w],
+                scale: [scale.x, scale.y, scale.z],
+            },
+            components,
+            children,
+        })
+    }
+
+    fn export_component(&self, info: &ObjectInfo) -> Result<ComponentData> {
+        let class_id = objects::class_id(self.file, info);
+
+        let fields = match self.component_fields {
+            true => {
+                let data = objects::object_data(self.data, info);
+                let value = match self.monobehaviour_types.get(&info.m_PathID) {
+                    Some(tt) => objects::read_object_with_typetree(self.file, info, data, tt)?,
+                    None => objects::read_object(self.file, info, data, &self.env.tpk)?,
+                };
+                Some(value)
+            }
+            false => None,
+        };
+
+        Ok(ComponentData {
+            path_id: info.m_PathID,
+            class: format!("{class_id:?}"),
+            script: (class_id == ClassId::MonoBehaviour)
+                .then(|| self.scripts.get(&info.m_PathID).cloned())
+                .flatten(),
+            fields,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct TagManager {
+    tags: Vec<String>,
+}
+
+fn custom_tags(env: &Environment) -> Vec<String> {
+    let read = || -> Result<Vec<String>> {
+        let (file, data) = env.load_serialized_uncached("globalgamemanagers")?;
+        let info = file
+            .objects()
+            .find(|info| objects::class_id(&file, info) == ClassId::TagManager)
+            .context("No TagManager in globalgamemanagers")?;
+        let tag_manager: TagManager = objects::read_object(
+            &file,
+            info,
+            objects::object_data(data.as_ref(), info),
+            &env.tpk,
+        )?;
+        Ok(tag_manager.tags)
+    };
+    read().unwrap_or_else(|e| {
+        warn!("Could not read custom tags, they will be exported as numbers: {e}");
+        Vec::new()
+    })
+}
+
+fn tag_name(tag: u16, custom_tags: &[String]) -> String {
+    let name = match tag {
+        0 => Some("Untagged"),
+        1 => Some("Respawn"),
+        2 => Some("Finish"),
+        3 => Some("EditorOnly"),
+        5 => Some("MainCamera"),
+        6 => Some("Player"),
+        7 => Some("GameController"),
+        20000.. => custom_tags.get(tag as usize - 20000).map(String::as_str),
+        _ => None,
+    };
+    name.map_or_else(|| tag.to_string(), str::to_owned)
+}
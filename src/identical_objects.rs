@@ -0,0 +1,228 @@
+//! Collapsing objects with identical content, e.g. the same material copied into several scenes.
+use anyhow::{Context, Result};
+use log::warn;
+use rabex::files::serializedfile::builder::SerializedFileBuilder;
+use rabex::objects::ClassId;
+use rabex::objects::pptr::PathId;
+use rabex::typetree::{TypeTreeNode, TypeTreeProvider};
+use rabex_env::unity::types::AssetBundle;
+use rustc_hash::{FxBuildHasher, FxHashMap};
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::hash::BuildHasher;
+
+use crate::objects;
+use crate::references::{ManagedTypes, object_references, rewrite_pptrs};
+
+/// Removes objects of `builder` that have the same class and data as another one, and points all
+/// references to them at the one with the lowest PathId. Returns the number of objects and bytes
+/// removed.
+///
+/// This is repeated until nothing changes, so objects only differing in references to
+/// duplicates are merged as well.
+pub(crate) fn merge_identical_objects(
+    builder: &mut SerializedFileBuilder<impl TypeTreeProvider>,
+    asset_bundle: &mut AssetBundle,
+    monobehaviour_types: &FxHashMap<PathId, &TypeTreeNode>,
+    tpk: &impl TypeTreeProvider,
+) -> Result<(usize, usize)> {
+    let untyped = builder
+        .objects
+        .values()
+        .filter(|(info, _)| {
+            objects::class_id(&builder.serialized, info) == ClassId::MonoBehaviour
+                && !monobehaviour_types.contains_key(&info.m_PathID)
+        })
+        .count();
+    if untyped > 0 {
+        warn!(
+            "Not deduplicating objects, as references from {untyped} MonoBehaviours without a typetree for their script could not be updated"
+        );
+        return Ok((0, 0));
+    }
+
+    let endianness = builder.serialized.m_Header.m_Endianess;
+    let managed = ManagedTypes::of(&builder.serialized);
+
+    // the objects referencing each object, which are rewritten when it is removed
+    let mut referrers: FxHashMap<PathId, Vec<PathId>> = FxHashMap::default();
+    for (&path_id, (info, data)) in &builder.objects {
+        let tt = match monobehaviour_types.get(&path_id) {
+            Some(&tt) => Cow::Borrowed(tt),
+            None => builder.serialized.get_typetree_for(info, tpk)?,
+        };
+        let references = object_references(data, &tt, endianness, &managed)
+            .with_context(|| format!("Could not read object {path_id}"))?;
+        for reference in references {
+            if reference.file_id == 0 {
+                referrers
+                    .entry(reference.path_id)
+                    .or_default()
+                    .push(path_id);
+            }
+        }
+    }
+
+    let mut hashes = builder
+        .objects
+        .iter()
+        .map(|(&path_id, (_, data))| (path_id, FxBuildHasher.hash_one(data.as_ref())))
+        .collect::<FxHashMap<_, _>>();
+
+    let mut removed_objects = 0;
+    let mut removed_bytes = 0;
+
+    loop {
+        // visit objects by PathId, so the copy with the lowest one is kept
+        let mut path_ids = builder.objects.keys().copied().collect::<Vec<_>>();
+        path_ids.sort_unstable();
+
+        let mut by_hash: FxHashMap<u64, Vec<PathId>> = FxHashMap::default();
+        let mut duplicates: FxHashMap<PathId, PathId> = FxHashMap::default();
+        for path_id in path_ids {
+            let (info, data) = &builder.objects[&path_id];
+            let class_id = objects::class_id(&builder.serialized, info);
+            let candidates = by_hash.entry(hashes[&path_id]).or_default();
+            let original = candidates.iter().find(|&&candidate| {
+                let (candidate_info, candidate_data) = &builder.objects[&candidate];
+                objects::class_id(&builder.serialized, candidate_info) == class_id
+                    && candidate_data.as_ref() == data.as_ref()
+            });
+            match original {
+                Some(&original) => {
+                    duplicates.insert(path_id, original);
+                }
+                None => candidates.push(path_id),
+            }
+        }
+        if duplicates.is_empty() {
+            break;
+        }
+
+        builder.objects.retain(|path_id, (_, data)| {
+            if duplicates.contains_key(path_id) {
+                removed_objects += 1;
+                removed_bytes += data.len();
+                return false;
+            }
+            true
+        });
+
+        let mut changed = BTreeSet::new();
+        for (duplicate, original) in &duplicates {
+            let Some(duplicate_referrers) = referrers.remove(duplicate) else {
+                continue;
+            };
+            changed.extend(duplicate_referrers.iter().copied());
+            referrers
+                .entry(*original)
+                .or_default()
+                .extend(duplicate_referrers);
+        }
+
+        for path_id in changed {
+            let Some((info, data)) = builder.objects.get_mut(&path_id) else {
+                continue;
+            };
+            let tt = match monobehaviour_types.get(&path_id) {
+                Some(&tt) => Cow::Borrowed(tt),
+                None => builder.serialized.get_typetree_for(info, tpk)?,
+            };
+            rewrite_pptrs(
+                data.to_mut().as_mut_slice(),
+                &tt,
+                endianness,
+                &managed,
+                |file_id, path_id| {
+                    let original = duplicates.get(&path_id).filter(|_| file_id == 0)?;
+                    Some((0, *original))
+                },
+            )
+            .with_context(|| format!("Could not update references of object {path_id}"))?;
+            hashes.insert(path_id, FxBuildHasher.hash_one(data.as_ref()));
+        }
+
+        for info in asset_bundle.m_Container.values_mut() {
+            if info.asset.m_FileID == 0
+                && let Some(&original) = duplicates.get(&info.asset.m_PathID)
+            {
+                info.asset.m_PathID = original;
+            }
+        }
+    }
+
+    Ok((removed_objects, removed_bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{self, TestScene};
+    use crate::{PackSettings, Stats};
+    use rabex::files::serializedfile;
+
+    #[test]
+    fn identical_materials_are_merged() {
+        let tpk = test_utils::tpk();
+        let mut scene = TestScene::new("scene");
+        // added first, so it gets the lowest PathId
+        let first = scene.add_material(&tpk, "Material");
+        let second = scene.add_material(&tpk, "Material");
+        scene.add_renderer(&tpk, "A", first);
+        scene.add_renderer(&tpk, "B", second);
+        let mut scene = scene.repack(&tpk);
+
+        let unity_version = test_utils::unity_version();
+        let common_offset_map = serializedfile::build_common_offset_map(&tpk.inner, &unity_version);
+        let mut builder =
+            SerializedFileBuilder::new(&unity_version, &tpk, &common_offset_map, false);
+        builder.next_path_id = 2;
+        let mut asset_bundle = AssetBundle::asset_base("test");
+        let settings = PackSettings {
+            dedupe_objects: true,
+            ..Default::default()
+        };
+        let mut stats = Stats::default();
+        crate::add_asset_scenes(
+            &mut builder,
+            &mut asset_bundle,
+            vec![&mut scene],
+            &tpk,
+            &unity_version,
+            &settings,
+            &mut stats,
+        )
+        .unwrap();
+        assert!(stats.deduplicated_bytes > 0);
+
+        let of_class = |class_id| {
+            builder
+                .objects
+                .iter()
+                .filter(move |(_, (info, _))| {
+                    objects::class_id(&builder.serialized, info) == class_id
+                })
+                .collect::<Vec<_>>()
+        };
+        let materials = of_class(ClassId::Material);
+        assert_eq!(materials.len(), 1);
+        let material = *materials[0].0;
+        assert_eq!(Some(&material), builder.objects.keys().min());
+
+        let renderers = of_class(ClassId::MeshRenderer);
+        assert_eq!(renderers.len(), 2);
+        for (_, (info, data)) in renderers {
+            let tt = builder.serialized.get_typetree_for(info, &tpk).unwrap();
+            let endianness = builder.serialized.m_Header.m_Endianess;
+            let managed = ManagedTypes::of(&builder.serialized);
+            let references =
+                object_references(data, &tt, endianness, &ManagedTypes::default()).unwrap();
+            let materials = references
+                .iter()
+                .filter(|reference| reference.field.starts_with("m_Materials"))
+                .map(|reference| (reference.file_id, reference.path_id))
+                .collect::<Vec<_>>();
+            assert_eq!(materials, [(0, material)]);
+        }
+    }
+}
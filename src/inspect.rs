@@ -0,0 +1,351 @@
+//! Look inside produced bundles, and check that their references are intact.
+#![allow(non_snake_case)]
+
+use anyhow::{Context, Result, bail};
+use indexmap::IndexMap;
+use log::warn;
+use rabex::config::ExtractionConfig;
+use rabex::files::SerializedFile;
+use rabex::files::bundlefile::BundleFileReader;
+use rabex::objects::ClassId;
+use rabex::objects::pptr::PathId;
+use rabex::typetree::TypeTreeProvider;
+use rabex_env::Environment;
+use rabex_env::unity::types::MonoBehaviour;
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde_derive::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::io::Cursor;
+
+use crate::references::{ManagedTypes, object_references};
+use crate::{objects, prepare_monobehaviour_types};
+
+/// A file contained in a UnityFS bundle
+pub struct BundleEntry {
+    pub name: String,
+    pub data: Vec<u8>,
+    /// `None` for resource files like `.resS`
+    pub serialized: Option<SerializedFile>,
+}
+
+pub fn read_bundle(data: &[u8]) -> Result<Vec<BundleEntry>> {
+    let mut bundle = BundleFileReader::from_reader(Cursor::new(data), &ExtractionConfig::default())
+        .context("Could not read bundle")?;
+
+    let mut entries = Vec::new();
+    while let Some(mut file) = bundle.next() {
+        let name = file.path.clone();
+        let data = file
+            .read()
+            .with_context(|| format!("Could not read '{name}' from bundle"))?;
+
+        let serialized = match is_resource_file(&name) {
+            true => None,
+            false => Some(
+                SerializedFile::from_reader(&mut Cursor::new(data.as_slice()))
+                    .with_context(|| format!("Could not parse '{name}' in bundle"))?,
+            ),
+        };
+
+        entries.push(BundleEntry {
+            name,
+            data,
+            serialized,
+        });
+    }
+    Ok(entries)
+}
+
+fn is_resource_file(name: &str) -> bool {
+    name.ends_with(".resS") || name.ends_with(".resource")
+}
+
+#[derive(Debug, Serialize)]
+pub struct BundleInfo {
+    pub files: Vec<BundleFileInfo>,
+    pub asset_bundle: Option<AssetBundleInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BundleFileInfo {
+    pub name: String,
+    pub size: usize,
+    /// Only present for serialized files
+    pub externals: Option<Vec<String>>,
+    pub objects_by_class: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AssetBundleInfo {
+    pub name: String,
+    pub container: Vec<ContainerEntry>,
+    pub scenes: Vec<String>,
+    pub dependencies: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContainerEntry {
+    pub path: String,
+    pub file_id: i32,
+    pub path_id: PathId,
+}
+
+#[derive(Deserialize)]
+struct AssetBundleContents {
+    m_Name: String,
+    m_Container: IndexMap<String, AssetInfoContents>,
+    #[serde(default)]
+    m_Dependencies: Vec<String>,
+    #[serde(default)]
+    m_SceneHashes: IndexMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct AssetInfoContents {
+    asset: RawPPtr,
+}
+
+#[derive(Deserialize)]
+struct RawPPtr {
+    m_FileID: i32,
+    m_PathID: PathId,
+}
+
+pub fn inspect_bundle(entries: &[BundleEntry], tpk: &impl TypeTreeProvider) -> Result<BundleInfo> {
+    let mut files = Vec::new();
+    let mut asset_bundle = None;
+
+    for entry in entries {
+        let Some(file) = &entry.serialized else {
+            files.push(BundleFileInfo {
+                name: entry.name.clone(),
+                size: entry.data.len(),
+                externals: None,
+                objects_by_class: BTreeMap::new(),
+            });
+            continue;
+        };
+
+        let mut objects_by_class = BTreeMap::new();
+        for info in file.objects() {
+            let class_id = objects::class_id(file, info);
+            *objects_by_class.entry(format!("{class_id:?}")).or_default() += 1;
+
+            if class_id == ClassId::AssetBundle && asset_bundle.is_none() {
+                let contents: AssetBundleContents =
+                    objects::read_object(file, info, objects::object_data(&entry.data, info), tpk)
+                        .with_context(|| {
+                            format!("Could not read AssetBundle in '{}'", entry.name)
+                        })?;
+                asset_bundle = Some(asset_bundle_info(contents));
+            }
+        }
+
+        files.push(BundleFileInfo {
+            name: entry.name.clone(),
+            size: entry.data.len(),
+            externals: Some(
+                file.m_Externals
+                    .iter()
+                    .map(|external| external.pathName.clone())
+                    .collect(),
+            ),
+            objects_by_class,
+        });
+    }
+
+    Ok(BundleInfo {
+        files,
+        asset_bundle,
+    })
+}
+
+fn asset_bundle_info(contents: AssetBundleContents) -> AssetBundleInfo {
+    let mut scenes: BTreeSet<String> = contents.m_SceneHashes.into_keys().collect();
+    scenes.extend(
+        contents
+            .m_Container
+            .keys()
+            .filter(|path| path.ends_with(".unity"))
+            .cloned(),
+    );
+
+    AssetBundleInfo {
+        name: contents.m_Name,
+        container: contents
+            .m_Container
+            .into_iter()
+            .map(|(path, info)| ContainerEntry {
+                path,
+                file_id: info.asset.m_FileID,
+                path_id: info.asset.m_PathID,
+            })
+            .collect(),
+        scenes: scenes.into_iter().collect(),
+        dependencies: contents.m_Dependencies,
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerificationError {
+    pub file: String,
+    pub path_id: PathId,
+    pub class: String,
+    pub field: String,
+    pub file_id: i32,
+    pub target_path_id: PathId,
+    pub reason: &'static str,
+}
+
+impl Display for VerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} ({}) field '{}' -> ({}, {}): {}",
+            self.file,
+            self.path_id,
+            self.class,
+            self.field,
+            self.file_id,
+            self.target_path_id,
+            self.reason
+        )
+    }
+}
+
+/// Reads the bundle and returns every reference that does not resolve, see [`verify_bundle`].
+///
+/// Meant to be run on freshly packed output.
+pub fn verify_bundle_data(data: &[u8], env: &Environment) -> Result<Vec<VerificationError>> {
+    verify_bundle_with_scripts(&read_bundle(data)?, env)
+}
+
+/// Post-pack check of a written bundle, failing if any reference in it does not resolve.
+pub fn verify_packed_bundle(data: &[u8], env: &Environment) -> Result<()> {
+    let errors = verify_bundle_data(data, env)?;
+    if let Some(first) = errors.first() {
+        bail!(
+            "{} unresolved references in bundle, first one: {first}",
+            errors.len()
+        );
+    }
+    Ok(())
+}
+
+/// Checks that every `PPtr` in the bundle points to something that exists.
+///
+/// Local pointers have to resolve to an object in the same file, external ones have to index a valid
+/// entry of `m_Externals`. When the external is another file in the same bundle, the target object
+/// has to exist there as well.
+///
+/// Without typetrees in the bundle, only the base fields of MonoBehaviours can be checked.
+/// Use [`verify_bundle_with_scripts`] to read the script fields from the game.
+pub fn verify_bundle(
+    entries: &[BundleEntry],
+    tpk: &impl TypeTreeProvider,
+) -> Result<Vec<VerificationError>> {
+    verify(entries, tpk, None)
+}
+
+/// Like [`verify_bundle`], but also checks the fields of MonoBehaviours, using the typetrees
+/// generated from the scripts of the game.
+pub fn verify_bundle_with_scripts(
+    entries: &[BundleEntry],
+    env: &Environment,
+) -> Result<Vec<VerificationError>> {
+    verify(entries, &env.tpk, Some(env))
+}
+
+fn verify(
+    entries: &[BundleEntry],
+    tpk: &impl TypeTreeProvider,
+    env: Option<&Environment>,
+) -> Result<Vec<VerificationError>> {
+    let path_ids_per_file: FxHashMap<&str, FxHashSet<PathId>> = entries
+        .iter()
+        .filter_map(|entry| {
+            let file = entry.serialized.as_ref()?;
+            Some((
+                entry.name.as_str(),
+                file.objects().map(|obj| obj.m_PathID).collect(),
+            ))
+        })
+        .collect();
+
+    let mut errors = Vec::new();
+    let mut unchecked_scripts = 0;
+    for entry in entries {
+        let Some(file) = &entry.serialized else {
+            continue;
+        };
+        let local = &path_ids_per_file[entry.name.as_str()];
+
+        let monobehaviour_types = env
+            .map(|env| prepare_monobehaviour_types(env, file, &mut Cursor::new(&entry.data)))
+            .unwrap_or_default();
+        let managed = ManagedTypes::of(file);
+
+        for info in file.objects() {
+            let data = objects::object_data(&entry.data, info);
+            let tt = match monobehaviour_types.get(&info.m_PathID) {
+                Some(&tt) => Cow::Borrowed(tt),
+                None => {
+                    if objects::class_id(file, info) == ClassId::MonoBehaviour
+                        && file.m_Types[info.m_TypeID as usize].m_Type.is_none()
+                    {
+                        let mb: MonoBehaviour = objects::read_object(file, info, data, tpk)
+                            .with_context(|| format!("In '{}'", entry.name))?;
+                        unchecked_scripts += usize::from(!mb.m_Script.is_null());
+                    }
+                    file.get_typetree_for(info, tpk)?
+                }
+            };
+            let references = object_references(data, &tt, file.m_Header.m_Endianess, &managed)
+                .with_context(|| format!("Could not read object {}", info.m_PathID))
+                .with_context(|| format!("In '{}'", entry.name))?;
+
+            for reference in references {
+                let reason = match reference.file_id {
+                    0 => (!local.contains(&reference.path_id)).then_some("object does not exist"),
+                    file_id => match usize::try_from(file_id - 1)
+                        .ok()
+                        .and_then(|index| file.m_Externals.get(index))
+                    {
+                        None => Some("file id is out of range of the externals"),
+                        Some(external) => {
+                            let external_name = external.pathName.rsplit('/').next().unwrap();
+                            match path_ids_per_file.get(external_name) {
+                                Some(path_ids) if !path_ids.contains(&reference.path_id) => {
+                                    Some("object does not exist in external file in bundle")
+                                }
+                                _ => None,
+                            }
+                        }
+                    },
+                };
+
+                if let Some(reason) = reason {
+                    errors.push(VerificationError {
+                        file: entry.name.clone(),
+                        path_id: info.m_PathID,
+                        class: format!("{:?}", objects::class_id(file, info)),
+                        field: reference.field,
+                        file_id: reference.file_id,
+                        target_path_id: reference.path_id,
+                        reason,
+                    });
+                }
+            }
+        }
+    }
+
+    if unchecked_scripts > 0 {
+        warn!(
+            "Only checked the base fields of {unchecked_scripts} MonoBehaviours, their scripts have no typetree"
+        );
+    }
+
+    Ok(errors)
+}
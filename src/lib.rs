@@ -1,6 +1,33 @@
+mod ancestors;
+pub mod bundle_diff;
+mod checksum;
+pub mod diff;
+mod exclusions;
+pub mod graph;
+pub mod hierarchy;
+mod identical_objects;
+pub mod inspect;
+pub mod manifest;
 mod merge_serialized;
 pub mod monobehaviour_typetree_export;
-
+mod multi_bundle;
+mod objects;
+mod path_ids;
+mod references;
+mod root_container;
+mod root_transforms;
+mod scene_objects;
+mod scene_settings;
+mod scene_tree;
+pub mod size_report;
+#[cfg(test)]
+mod test_utils;
+mod unity_types;
+mod whole_scene;
+
+pub use checksum::BundleChecksum;
+pub use multi_bundle::{BundleSplit, MultiBundle, PackedBundle, pack_to_multi_asset_bundle};
+pub use path_ids::PathIdStrategy;
 pub use rabex;
 use rabex::objects::ClassId;
 use rabex_env::Environment;
@@ -10,12 +37,15 @@ use rabex_env::handle::SerializedFileHandle;
 use rabex_env::resolver::EnvResolver as _;
 use rabex_env::scene_lookup::SceneLookup;
 use rabex_env::unity::types::{AssetBundle, AssetInfo, MonoBehaviour, PreloadData, Transform};
+pub use root_container::RootContainer;
+pub use root_transforms::RootTransforms;
+pub use scene_objects::{KeepAncestors, SceneObject, SceneObjectOptions};
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail, ensure};
 use indexmap::{IndexMap, IndexSet};
 use log::warn;
 use rabex::UnityVersion;
-use rabex::files::bundlefile::{BundleFileBuilder, CompressionType};
+use rabex::files::bundlefile::CompressionType;
 use rabex::files::serializedfile::FileIdentifier;
 use rabex::files::serializedfile::builder::SerializedFileBuilder;
 use rabex::files::{SerializedFile, serializedfile};
@@ -31,25 +61,52 @@ use std::io::{Cursor, Read, Seek, Write};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+use crate::merge_serialized::MetaIndex;
+use crate::path_ids::PathIdAllocator;
+use crate::scene_tree::SceneTree;
+use crate::size_report::{Budget, SizeReport};
+
 pub struct RepackSettings {
-    pub scene_objects: IndexMap<String, Vec<String>>,
+    pub scene_objects: IndexMap<String, Vec<SceneObject>>,
     pub extra_objects: IndexMap<String, IndexSet<String>>,
+    /// Only used by [`pack_to_hybrid_bundle`], scenes not listed here are packed as scenes
+    pub scene_targets: IndexMap<String, SceneTarget>,
 }
 impl RepackSettings {
     pub fn is_empty(&self) -> bool {
         self.scene_objects.is_empty() && self.extra_objects.is_empty()
     }
+
+    pub fn scene_target(&self, scene_name: &str) -> SceneTarget {
+        self.scene_targets
+            .get(scene_name)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// How the objects of a scene are packed into a [hybrid bundle](pack_to_hybrid_bundle)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SceneTarget {
+    /// A filtered scene, loaded using `LoadScene`
+    #[default]
+    Scene,
+    /// Individual assets, loaded using `LoadAsset`
+    Asset,
 }
 
 pub struct RepackScene<'a> {
     pub original_name: PathBuf,
     pub scene_name: String,
+    pub target: SceneTarget,
 
     pub serialized: SerializedFile,
     pub serialized_data: Data,
 
     pub keep_objects: BTreeSet<i64>,
     pub roots: Vec<(String, Transform)>,
+    /// Whether the roots were deactivated when repacking
+    pub roots_disabled: bool,
     pub replacements: FxHashMap<PathId, Vec<u8>>,
 
     pub monobehaviour_types: FxHashMap<i64, &'a TypeTreeNode>,
@@ -63,14 +120,20 @@ pub fn repack_scenes<'a>(
     repack_settings: RepackSettings,
     prepare_scripts: bool,
     disable_roots: bool,
+    root_transforms: RootTransforms,
+    keep_scene_settings: bool,
 ) -> Result<(Vec<RepackScene<'a>>, Vec<ExtraObject>)> {
     let (scenes, extra_objects) = collect_what_to_repack(
         env,
         &repack_settings,
         |filename, scene_name, object_paths, file, data| {
+            let target = repack_settings.scene_target(scene_name);
             let settings = RepackSceneSettings {
                 object_paths,
+                target,
                 disable_roots,
+                root_transforms,
+                keep_scene_settings: keep_scene_settings && target == SceneTarget::Scene,
             };
             repack_scene(
                 env,
@@ -84,6 +147,12 @@ pub fn repack_scenes<'a>(
         },
     )?;
 
+    for scene_name in repack_settings.scene_targets.keys() {
+        if !repack_settings.scene_objects.contains_key(scene_name) {
+            warn!("Scene '{scene_name}' has a target, but no objects to repack");
+        }
+    }
+
     for (class_name, names) in repack_settings.extra_objects {
         for name in names {
             let found = extra_objects
@@ -105,7 +174,7 @@ fn collect_what_to_repack<T: Send + Sync>(
     env: &Environment,
     repack_settings: &RepackSettings,
     // |filename, scene_name, object_paths, file, data|
-    f: impl Fn(&Path, &str, &[String], SerializedFile, Data) -> Result<T> + Send + Sync,
+    f: impl Fn(&Path, &str, &[SceneObject], SerializedFile, Data) -> Result<T> + Send + Sync,
 ) -> Result<(Vec<T>, Vec<ExtraObject>)> {
     let build_settings = env.build_settings()?;
     let has_extra_objects = !repack_settings.extra_objects.is_empty();
@@ -133,8 +202,12 @@ fn collect_what_to_repack<T: Send + Sync>(
     let (extra_objects, scenes) = if has_extra_objects {
         let scene_lookup: Vec<_> = build_settings.scene_names().collect();
 
-        env.game_files
-            .serialized_files()?
+        // the order of the output only depends on the order of the inputs, not on the file
+        // listing or thread scheduling
+        let mut filenames: Vec<PathBuf> = env.game_files.serialized_files()?.into_iter().collect();
+        filenames.sort();
+
+        let files = filenames
             .into_par_iter()
             .map(|filename| -> Result<_> {
                 let (data, file_raw) = read(&filename, None)?;
@@ -150,33 +223,29 @@ fn collect_what_to_repack<T: Send + Sync>(
                 {
                     let scene_name = scene_lookup[scene_index];
 
-                    if let Some(object_paths) = repack_settings.scene_objects.get(scene_name) {
+                    if let Some((index, _, object_paths)) =
+                        repack_settings.scene_objects.get_full(scene_name)
+                    {
                         let x = f(&filename, scene_name, object_paths, file_raw, data)?;
-                        return Ok((extra_objects, Some(x)));
+                        return Ok((extra_objects, Some((index, x))));
                     }
                 }
 
                 Ok((extra_objects, None))
             })
-            .try_fold(
-                || (Vec::new(), Vec::new()),
-                |mut acc, item| -> Result<_> {
-                    let item = item?;
-                    acc.0.extend(item.0);
-                    if let Some(scene) = item.1 {
-                        acc.1.push(scene);
-                    }
-                    Ok(acc)
-                },
-            )
-            .try_reduce(
-                || (Vec::new(), Vec::new()),
-                |mut acc, item| {
-                    acc.0.extend(item.0);
-                    acc.1.extend(item.1);
-                    Ok(acc)
-                },
-            )?
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut extra_objects = Vec::new();
+        let mut scenes = Vec::new();
+        for (file_extra_objects, scene) in files {
+            extra_objects.extend(file_extra_objects);
+            scenes.extend(scene);
+        }
+        // scenes are packed in the order they are requested in, like without extra objects
+        scenes.sort_by_key(|&(index, _)| index);
+        let scenes = scenes.into_iter().map(|(_, scene)| scene).collect();
+
+        (extra_objects, scenes)
     } else {
         let scene_lookup = build_settings.scene_name_lookup();
 
@@ -214,8 +283,12 @@ fn collect_what_to_repack<T: Send + Sync>(
 }
 
 struct RepackSceneSettings<'a> {
-    object_paths: &'a [String],
+    object_paths: &'a [SceneObject],
+    target: SceneTarget,
     disable_roots: bool,
+    root_transforms: RootTransforms,
+    /// Keep `RenderSettings`, `LightmapSettings` etc. and the assets they reference
+    keep_scene_settings: bool,
 }
 
 fn repack_scene<'a>(
@@ -224,23 +297,133 @@ fn repack_scene<'a>(
     original_name: &Path,
     scene_name: &str,
     settings: RepackSceneSettings,
-    file: SerializedFile,
+    mut file: SerializedFile,
     serialized_data: Data,
 ) -> Result<RepackScene<'a>> {
     let reader = &mut Cursor::new(serialized_data.as_ref());
 
-    let scene_paths = deduplicate_objects(original_name, scene_name, settings.object_paths);
+    let (exclusions, scene_objects): (Vec<_>, Vec<_>) = settings
+        .object_paths
+        .iter()
+        .partition(|object| object.is_exclusion());
+    let scene_objects = deduplicate_objects(original_name, scene_name, scene_objects);
+    let exclusions = deduplicate_objects(original_name, scene_name, exclusions);
+
+    let whole_scene = scene_objects.contains_key(SceneObject::WHOLE_SCENE);
+    if whole_scene && scene_objects.len() > 1 {
+        warn!(
+            "'{}' already includes every object, ignoring the other entries in {}",
+            SceneObject::WHOLE_SCENE,
+            scene_name_display(scene_name, original_name)
+        );
+    }
+
+    // changes to the hierarchy which have to be made before computing what is reachable
+    let mut patches = FxHashMap::default();
+    let mut excluded = Vec::new();
+    let mut whole_scene_roots = None;
+    let mut kept_ancestors = None;
+    let mut prune_paths = scene_objects
+        .keys()
+        .map(|&path| path.to_owned())
+        .collect::<IndexSet<_>>();
+    if whole_scene
+        || !exclusions.is_empty()
+        || scene_objects
+            .values()
+            .any(|object| object.keep_ancestors().is_some())
+    {
+        let data = serialized_data.as_ref();
+        let tree = SceneTree::build(&file, data, &env.tpk)
+            .with_context(|| scene_name_display(scene_name, original_name))?;
+        excluded =
+            exclusions::apply_exclusions(env, &file, data, &tree, &exclusions, &mut patches)?;
+
+        if whole_scene {
+            let excluded_roots = excluded
+                .iter()
+                .filter(|excluded| excluded.subtree)
+                .map(|excluded| excluded.transform)
+                .collect();
+            let roots = whole_scene::whole_scene(
+                &file,
+                data,
+                &env.tpk,
+                &tree,
+                &excluded_roots,
+                &mut patches,
+            )?;
+            prune_paths = roots.paths.iter().cloned().collect();
+            whole_scene_roots = Some(roots);
+        } else {
+            let kept =
+                ancestors::keep_ancestors(env, &file, data, &tree, &scene_objects, &mut patches)?;
+            prune_paths = kept.paths;
+            kept_ancestors = Some(kept.requested);
+        }
+    }
 
     let mut replacements = FxHashMap::default();
-    let result = rabex_env::prune::prune_scene(
-        env,
+    let mut result = objects::with_patched_objects(
+        &mut file,
+        serialized_data.as_ref(),
+        &patches,
+        |file, reader| {
+            rabex_env::prune::prune_scene(
+                env,
+                file,
+                reader,
+                prune_paths.iter().map(String::as_str),
+                &mut replacements,
+                settings.disable_roots,
+            )
+        },
+    )
+    .with_context(|| scene_name_display(scene_name, original_name))?;
+    for (path_id, patch) in patches {
+        replacements.entry(path_id).or_insert(patch);
+    }
+    if let Some(whole_scene_roots) = whole_scene_roots {
+        whole_scene_roots.restore_names(&file, &env.tpk, &mut replacements, &mut result.roots)?;
+    }
+
+    for excluded in excluded {
+        if excluded
+            .objects
+            .iter()
+            .any(|object| result.reachable.contains(object))
+        {
+            warn!(
+                "Excluded '{}' in {} is still referenced by other objects and will be kept",
+                excluded.path,
+                scene_name_display(scene_name, original_name)
+            );
+        }
+    }
+
+    if settings.keep_scene_settings {
+        scene_settings::keep_scene_settings(
+            &file,
+            serialized_data.as_ref(),
+            &env.tpk,
+            &replacements,
+            &mut result.reachable,
+        )
+        .with_context(|| scene_name_display(scene_name, original_name))?;
+    }
+
+    root_transforms::rewrite_root_transforms(
         &file,
-        reader,
-        scene_paths.iter().copied(),
+        serialized_data.as_ref(),
+        &env.tpk,
+        &result.roots,
+        settings.root_transforms,
         &mut replacements,
-        settings.disable_roots,
     )
     .with_context(|| scene_name_display(scene_name, original_name))?;
+    if let Some(requested) = kept_ancestors {
+        ancestors::rename_roots(&requested, &mut result.roots);
+    }
 
     let monobehaviour_types = prepare_scripts
         .then(|| prepare_monobehaviour_types(env, &file, reader))
@@ -249,10 +432,12 @@ fn repack_scene<'a>(
     Ok(RepackScene {
         original_name: original_name.to_owned(),
         scene_name: scene_name.to_owned(),
+        target: settings.target,
         serialized: file,
         serialized_data,
         keep_objects: result.reachable,
         roots: result.roots,
+        roots_disabled: settings.disable_roots,
         replacements,
         monobehaviour_types,
     })
@@ -314,13 +499,14 @@ fn prune_types(file: &mut SerializedFile) -> FxHashMap<i32, i32> {
 fn deduplicate_objects<'a>(
     original_name: &Path,
     scene_name: &str,
-    paths: &'a [String],
-) -> IndexSet<&'a str> {
-    let mut deduplicated = IndexSet::new();
-    for item in paths {
-        if !deduplicated.insert(item.as_str()) {
+    objects: impl IntoIterator<Item = &'a SceneObject>,
+) -> IndexMap<&'a str, &'a SceneObject> {
+    let mut deduplicated = IndexMap::new();
+    for item in objects {
+        if deduplicated.insert(item.path(), item).is_some() {
             warn!(
-                "Duplicate object: '{item}' in {}",
+                "Duplicate object: '{}' in {}",
+                item.path(),
                 scene_name_display(Some(scene_name), original_name)
             );
         }
@@ -334,32 +520,193 @@ pub struct Stats {
     pub objects_after: usize,
     pub size_before: usize,
     pub size_after: usize,
+    /// Only computed if [`PackSettings::size_report`] is set
+    pub size_report: Option<SizeReport>,
+    /// Size of the objects removed by [`PackSettings::dedupe_objects`]
+    pub deduplicated_bytes: usize,
+    /// Not set by [`pack_to_multi_asset_bundle`], see [`PackedBundle::checksum`]
+    pub checksum: BundleChecksum,
 }
 
+#[derive(Debug, Default)]
+pub struct PackSettings {
+    /// Compute a breakdown of the output size, see [`size_report::size_report`]
+    ///
+    /// Repack with `prepare_scripts`, so that objects used by MonoBehaviours are attributed
+    /// to their roots.
+    pub size_report: bool,
+    /// Fail before writing anything if the pruned objects exceed these limits
+    pub budget: Budget,
+    /// Scene bundles only: merge the listed source scenes into a single scene with the name of
+    /// the key. Scenes not in any group are packed on their own.
+    ///
+    /// Merged scenes should be repacked with `prepare_scripts`, so that references in
+    /// `MonoBehaviour`s can be renumbered. `MonoBehaviour`s without a script typetree are left out.
+    /// Only the scene settings of the first scene of a group are kept.
+    pub scene_groups: IndexMap<String, Vec<String>>,
+    /// Scene bundles only: move all roots of each source scene under a new GameObject
+    pub root_container: Option<RootContainer>,
+    /// How objects are numbered in asset bundles and merged scenes
+    pub path_ids: PathIdStrategy,
+    /// Asset and hybrid bundles only: keep a single copy of objects with identical class and
+    /// content, like the same material embedded in several scenes
+    pub dedupe_objects: bool,
+}
+
+impl PackSettings {
+    fn check_size(
+        &self,
+        scenes: &[RepackScene],
+        tpk: &impl TypeTreeProvider,
+    ) -> Result<Option<SizeReport>> {
+        if !self.size_report && self.budget.is_empty() {
+            return Ok(None);
+        }
+        let report = size_report::size_report(scenes, tpk)?;
+        self.budget.check(&report)?;
+        Ok(self.size_report.then_some(report))
+    }
+}
+
+/// Extra objects are listed in the container of the bundle like in a
+/// [hybrid bundle](pack_to_hybrid_bundle), so they can be loaded using `LoadAsset`.
 pub fn pack_to_scene_bundle(
     writer: impl Write + Seek,
     bundle_name: &str,
     tpk_blob: &TpkTypeTreeBlob,
-    tpk: &impl TypeTreeProvider,
+    tpk: &(impl TypeTreeProvider + Sync),
+    unity_version: &UnityVersion,
+    scenes: &mut [RepackScene],
+    extra_objects: Vec<ExtraObject>,
+    compression: CompressionType,
+    settings: &PackSettings,
+) -> Result<Stats> {
+    ensure!(
+        extra_objects.is_empty() || !scenes.is_empty(),
+        "Nothing to pack into the scene bundle, pack only extra objects into an asset bundle"
+    );
+    let assets = (!extra_objects.is_empty()).then_some(true);
+
+    pack_bundle(
+        writer,
+        bundle_name,
+        tpk_blob,
+        tpk,
+        unity_version,
+        scenes,
+        extra_objects,
+        compression,
+        settings,
+        assets,
+    )
+}
+
+/// Packs the scenes with [`SceneTarget::Asset`] like [`pack_to_asset_bundle`] and all other
+/// scenes like [`pack_to_scene_bundle`] into one bundle.
+///
+/// Its `AssetBundle` has both the scene entries and the container entries, so scenes and
+/// assets can be loaded from the same bundle.
+/// Asset scenes must be repacked with `prepare_scripts`.
+pub fn pack_to_hybrid_bundle(
+    writer: impl Write + Seek,
+    bundle_name: &str,
+    tpk_blob: &TpkTypeTreeBlob,
+    tpk: &(impl TypeTreeProvider + Sync),
+    unity_version: &UnityVersion,
+    scenes: &mut [RepackScene],
+    extra_objects: Vec<ExtraObject>,
+    compression: CompressionType,
+    enable_typetree: bool,
+    settings: &PackSettings,
+) -> Result<Stats> {
+    ensure!(
+        scenes
+            .iter()
+            .any(|scene| scene.target == SceneTarget::Scene),
+        "Hybrid bundles need at least one scene that is packed as a scene"
+    );
+
+    pack_bundle(
+        writer,
+        bundle_name,
+        tpk_blob,
+        tpk,
+        unity_version,
+        scenes,
+        extra_objects,
+        compression,
+        settings,
+        Some(enable_typetree),
+    )
+}
+
+/// `assets` is `Some(enable_typetree)` for hybrid bundles and scene bundles with extra objects.
+/// The `AssetBundle` is then written to a separate file instead of the first `.sharedAssets`,
+/// together with the objects of the scenes with [`SceneTarget::Asset`].
+fn pack_bundle(
+    writer: impl Write + Seek,
+    bundle_name: &str,
+    tpk_blob: &TpkTypeTreeBlob,
+    tpk: &(impl TypeTreeProvider + Sync),
     unity_version: &UnityVersion,
     scenes: &mut [RepackScene],
+    extra_objects: Vec<ExtraObject>,
     compression: CompressionType,
+    settings: &PackSettings,
+    assets: Option<bool>,
 ) -> Result<Stats> {
+    let is_asset = |scene: &RepackScene| assets.is_some() && scene.target == SceneTarget::Asset;
+
+    if let Some(container) = &settings.root_container {
+        for scene in scenes.iter_mut().filter(|scene| !is_asset(scene)) {
+            root_container::add_root_container(scene, container, tpk)
+                .with_context(|| format!("Could not add root container to {}", scene.scene_name))?;
+        }
+    }
+
     let mut stats = Stats::default();
+    stats.size_report = settings.check_size(scenes, tpk)?;
     let common_offset_map = serializedfile::build_common_offset_map(tpk_blob, unity_version);
 
+    let groups = group_scenes(scenes, &settings.scene_groups, is_asset)?;
+
     let mut asset_bundle = AssetBundle::scene_base(bundle_name);
-    for scene in &*scenes {
-        let scene_name = &scene.scene_name;
+    for (scene_name, _) in &groups {
         let scene_hash = get_scene_bundle_filename(bundle_name, scene_name);
         let path = get_scene_bundle_scene_name(bundle_name, scene_name);
         asset_bundle.add_scene(&path, &scene_hash);
     }
 
-    let mut asset_bundle = Some(asset_bundle);
+    let mut files = Vec::new();
+    let mut asset_bundle = match assets {
+        Some(enable_typetree) => {
+            let mut assets_file =
+                SerializedFileBuilder::new(unity_version, tpk, &common_offset_map, enable_typetree);
+            assets_file.next_path_id = 2;
+
+            add_extra_objects(&mut assets_file, &mut asset_bundle, extra_objects)?;
+            let asset_scenes = scenes.iter_mut().filter(|scene| is_asset(scene)).collect();
+            add_asset_scenes(
+                &mut assets_file,
+                &mut asset_bundle,
+                asset_scenes,
+                tpk,
+                unity_version,
+                settings,
+                &mut stats,
+            )?;
+            assets_file.add_object_at(1, &asset_bundle)?;
+
+            let mut out = Vec::new();
+            assets_file.write(&mut Cursor::new(&mut out))?;
+            stats.size_after += out.len();
+            files.push((format!("CAB-{bundle_name}"), out));
+            None
+        }
+        None => Some(asset_bundle),
+    };
 
-    let mut builder = BundleFileBuilder::unityfs(7, unity_version);
-    for scene in scenes {
+    for (scene_name, indices) in groups {
         let mut sharedassets =
             SerializedFileBuilder::new(unity_version, tpk, &common_offset_map, true);
 
@@ -371,66 +718,246 @@ pub fn pack_to_scene_bundle(
 
         let mut out = Cursor::new(Vec::new());
         sharedassets.write(&mut out)?;
+        let out = out.into_inner();
 
-        let scene_hash = get_scene_bundle_filename(bundle_name, &scene.scene_name);
-        builder.add_file(
-            &format!("{scene_hash}.sharedAssets",),
-            Cursor::new(out.into_inner()),
-        )?;
+        let scene_hash = get_scene_bundle_filename(bundle_name, &scene_name);
+        files.push((format!("{scene_hash}.sharedAssets"), out));
 
-        let trimmed = {
-            let serialized = &mut scene.serialized;
+        let trimmed = match indices.as_slice() {
+            &[index] => {
+                let scene = &mut scenes[index];
+                let serialized = &mut scene.serialized;
 
-            let data = scene.serialized_data.as_ref();
+                let data = scene.serialized_data.as_ref();
 
-            stats.objects_before += serialized.objects().len();
-            stats.size_before += data.len();
+                stats.objects_before += serialized.objects().len();
+                stats.size_before += data.len();
 
-            serialized.modify_objects(|objects| {
-                objects.retain(|obj| scene.keep_objects.contains(&obj.m_PathID));
-            });
-            stats.objects_after += serialized.objects().len();
-
-            let type_remap = prune_types(serialized);
-
-            let new_objects = serialized.take_objects();
-            let objects = new_objects.into_iter().map(|mut obj| {
-                let data = match scene.replacements.remove(&obj.m_PathID) {
-                    Some(owned) => Cow::Owned(owned),
-                    None => {
-                        let offset = obj.m_Offset as usize;
-                        let size = obj.m_Size as usize;
-                        Cow::Borrowed(&data[offset..offset + size])
-                    }
-                };
+                serialized.modify_objects(|objects| {
+                    objects.retain(|obj| scene.keep_objects.contains(&obj.m_PathID));
+                });
+                stats.objects_after += serialized.objects().len();
+
+                let type_remap = prune_types(serialized);
+
+                let new_objects = serialized.take_objects();
+                let objects = new_objects.into_iter().map(|mut obj| {
+                    let data = match scene.replacements.remove(&obj.m_PathID) {
+                        Some(owned) => Cow::Owned(owned),
+                        None => {
+                            let offset = obj.m_Offset as usize;
+                            let size = obj.m_Size as usize;
+                            Cow::Borrowed(&data[offset..offset + size])
+                        }
+                    };
 
-                obj.m_TypeID = type_remap[&obj.m_TypeID];
+                    obj.m_TypeID = type_remap[&obj.m_TypeID];
 
-                (obj, data)
-            });
+                    (obj, data)
+                });
 
-            let mut writer = Cursor::new(Vec::new());
-            serializedfile::write_serialized_with_objects(
-                &mut writer,
-                serialized,
-                &common_offset_map,
-                objects,
-            )?;
-            let out = writer.into_inner();
+                let mut writer = Cursor::new(Vec::new());
+                serializedfile::write_serialized_with_objects(
+                    &mut writer,
+                    serialized,
+                    &common_offset_map,
+                    objects,
+                )?;
+                let out = writer.into_inner();
 
-            stats.objects_after += serialized.objects().len();
-            stats.size_after += out.len();
+                stats.objects_after += serialized.objects().len();
+                stats.size_after += out.len();
 
-            out
+                out
+            }
+            _ => {
+                let group = scenes
+                    .iter_mut()
+                    .enumerate()
+                    .filter(|(i, _)| indices.contains(i))
+                    .map(|(_, scene)| scene)
+                    .collect();
+                let mut merged =
+                    SerializedFileBuilder::new(unity_version, tpk, &common_offset_map, false);
+                merged.next_path_id = 1;
+                merge_scenes(&mut merged, group, tpk, settings.path_ids, &mut stats)
+                    .with_context(|| format!("Could not merge scenes into '{scene_name}'"))?;
+
+                let mut out = Vec::new();
+                merged.write(&mut Cursor::new(&mut out))?;
+                stats.size_after += out.len();
+                out
+            }
         };
-        builder.add_file(&scene_hash, Cursor::new(trimmed))?;
+        files.push((scene_hash, trimmed));
     }
 
-    builder.write(writer, compression)?;
+    stats.checksum = checksum::write_unityfs(writer, unity_version, files, compression)?;
 
     Ok(stats)
 }
 
+/// Resolves [`PackSettings::scene_groups`] into the output scenes and the indices of their
+/// source scenes. Scenes packed as assets are skipped.
+fn group_scenes(
+    scenes: &[RepackScene],
+    scene_groups: &IndexMap<String, Vec<String>>,
+    is_asset: impl Fn(&RepackScene) -> bool,
+) -> Result<Vec<(String, Vec<usize>)>> {
+    let mut groups: IndexMap<String, Vec<usize>> = IndexMap::new();
+    for (index, scene) in scenes.iter().enumerate() {
+        if is_asset(scene) {
+            continue;
+        }
+
+        let mut containing = scene_groups
+            .iter()
+            .filter(|(_, members)| members.contains(&scene.scene_name))
+            .map(|(name, _)| name);
+        let name = containing.next().unwrap_or(&scene.scene_name);
+        if let Some(other) = containing.next() {
+            bail!(
+                "Scene '{}' is part of both the '{name}' and '{other}' scene groups",
+                scene.scene_name
+            );
+        }
+        groups.entry(name.clone()).or_default().push(index);
+    }
+
+    for (name, members) in scene_groups {
+        if !members.contains(name) && scenes.iter().any(|scene| scene.scene_name == *name) {
+            bail!("Scene group '{name}' has the same name as one of the scenes");
+        }
+        for member in members {
+            match scenes.iter().find(|scene| scene.scene_name == *member) {
+                Some(scene) if is_asset(scene) => {
+                    bail!("Scene '{member}' in scene group '{name}' is packed as assets")
+                }
+                Some(_) => {}
+                None => warn!("Scene '{member}' in scene group '{name}' has no objects to repack"),
+            }
+        }
+    }
+
+    Ok(groups.into_iter().collect())
+}
+
+/// Moves the kept objects of all `scenes` into `builder`, with renumbered PathIds
+fn merge_scenes(
+    builder: &mut SerializedFileBuilder<impl TypeTreeProvider>,
+    scenes: Vec<&mut RepackScene>,
+    tpk: &impl TypeTreeProvider,
+    path_ids: PathIdStrategy,
+    stats: &mut Stats,
+) -> Result<()> {
+    let mut path_ids = PathIdAllocator::new(path_ids);
+    let mut meta = MetaIndex::default();
+    let mut intermediate = Vec::with_capacity(scenes.len());
+    for (index, scene) in scenes.into_iter().enumerate() {
+        let dropped = drop_untyped_monobehaviours(scene, tpk)?;
+        if index > 0 {
+            scene_settings::drop_scene_settings(scene, tpk)?;
+        }
+
+        let serialized = &mut scene.serialized;
+        let data = scene.serialized_data.as_ref();
+
+        stats.objects_before += serialized.objects().len();
+        stats.size_before += data.len();
+        serialized.modify_objects(|objects| {
+            objects.retain(|obj| scene.keep_objects.contains(&obj.m_PathID))
+        });
+        stats.objects_after += serialized.objects().len();
+
+        let mut remap = merge_serialized::add_scene_meta_to_builder(
+            builder,
+            &mut meta,
+            serialized,
+            serialized.objects(),
+            &mut path_ids,
+            &scene.scene_name,
+            &scene.original_name,
+        )?;
+        // other scripts referencing the dropped ones get a null reference
+        remap
+            .path_id
+            .extend(dropped.into_iter().map(|path_id| (path_id, 0)));
+        intermediate.push((scene, remap));
+    }
+
+    for (scene, remap) in intermediate {
+        let objects = merge_serialized::remap_objects(
+            &scene.scene_name,
+            scene.original_name.clone(),
+            &builder.serialized,
+            scene.serialized_data.as_ref(),
+            tpk,
+            scene.serialized.take_objects(),
+            std::mem::take(&mut scene.replacements),
+            std::mem::take(&mut scene.monobehaviour_types),
+            remap,
+        )
+        .collect::<Result<Vec<_>>>()?;
+        for (obj, data) in objects {
+            builder.objects.insert(obj.m_PathID, (obj, data));
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the kept MonoBehaviours without a typetree for their script from the scene and from
+/// the components of their GameObject, as their references cannot be renumbered.
+///
+/// Returns the removed MonoBehaviours.
+fn drop_untyped_monobehaviours(
+    scene: &mut RepackScene,
+    tpk: &impl TypeTreeProvider,
+) -> Result<FxHashSet<PathId>> {
+    let file = &scene.serialized;
+    let data = scene.serialized_data.as_ref();
+
+    let untyped = file
+        .objects()
+        .filter(|obj| {
+            scene.keep_objects.contains(&obj.m_PathID)
+                && objects::class_id(file, obj) == ClassId::MonoBehaviour
+                && !scene.monobehaviour_types.contains_key(&obj.m_PathID)
+        })
+        .map(|obj| obj.m_PathID)
+        .collect::<FxHashSet<_>>();
+    if untyped.is_empty() {
+        return Ok(untyped);
+    }
+    warn!(
+        "Skipping {} MonoBehaviours in {} without a typetree for their script, their script is missing or could not be read",
+        untyped.len(),
+        scene_name_display(&scene.scene_name, &scene.original_name)
+    );
+
+    for info in file.objects() {
+        if !scene.keep_objects.contains(&info.m_PathID)
+            || objects::class_id(file, info) != ClassId::GameObject
+        {
+            continue;
+        }
+        let object_data = objects::current_object_data(data, &scene.replacements, info);
+        let mut go: unity_types::GameObject = objects::read_object(file, info, object_data, tpk)?;
+        let len = go.m_Component.len();
+        go.m_Component
+            .retain(|pair| !untyped.contains(&pair.component.m_PathID));
+        if go.m_Component.len() != len {
+            let patch = objects::write_object(file, info, &go, tpk)?;
+            scene.replacements.insert(info.m_PathID, patch);
+        }
+    }
+    scene
+        .keep_objects
+        .retain(|path_id| !untyped.contains(path_id));
+
+    Ok(untyped)
+}
+
 pub enum MonobehaviourTypetreeMode<'a> {
     GenerateRuntime,
     Export(&'a [u8]),
@@ -441,46 +968,111 @@ pub fn pack_to_asset_bundle(
     writer: impl Write + Seek,
     bundle_name: &str,
     tpk_blob: &TpkTypeTreeBlob,
-    scenes: Vec<RepackScene>,
+    mut scenes: Vec<RepackScene>,
     extra_objects: Vec<ExtraObject>,
     compression: CompressionType,
     enable_typetree: bool,
+    settings: &PackSettings,
+) -> Result<Stats> {
+    let size_report = settings.check_size(&scenes, &env.tpk)?;
+    let mut stats = pack_asset_bundle(
+        writer,
+        bundle_name,
+        tpk_blob,
+        &env.tpk,
+        env.unity_version()?,
+        scenes.iter_mut().collect(),
+        extra_objects,
+        compression,
+        enable_typetree,
+        settings,
+    )?;
+    stats.size_report = size_report;
+    Ok(stats)
+}
+
+fn pack_asset_bundle(
+    writer: impl Write + Seek,
+    bundle_name: &str,
+    tpk_blob: &TpkTypeTreeBlob,
+    tpk: &(impl TypeTreeProvider + Sync),
+    unity_version: &UnityVersion,
+    scenes: Vec<&mut RepackScene>,
+    extra_objects: Vec<ExtraObject>,
+    compression: CompressionType,
+    enable_typetree: bool,
+    settings: &PackSettings,
 ) -> Result<Stats> {
-    let unity_version = env.unity_version()?;
     let common_offset_map = serializedfile::build_common_offset_map(tpk_blob, unity_version);
     let mut stats = Stats::default();
 
     let mut builder =
-        SerializedFileBuilder::new(unity_version, &env.tpk, &common_offset_map, enable_typetree);
+        SerializedFileBuilder::new(unity_version, tpk, &common_offset_map, enable_typetree);
     builder.next_path_id = 2;
 
     let mut asset_bundle = AssetBundle::asset_base(bundle_name);
+    add_extra_objects(&mut builder, &mut asset_bundle, extra_objects)?;
+    add_asset_scenes(
+        &mut builder,
+        &mut asset_bundle,
+        scenes,
+        tpk,
+        unity_version,
+        settings,
+        &mut stats,
+    )?;
+
+    builder.add_object_at(1, &asset_bundle)?;
+
+    let mut out = Vec::new();
+    builder.write(&mut Cursor::new(&mut out))?;
+    stats.size_after += out.len();
+
+    stats.checksum = checksum::write_unityfs(
+        writer,
+        unity_version,
+        vec![(format!("CAB-{bundle_name}"), out)],
+        compression,
+    )?;
+
+    Ok(stats)
+}
+
+/// Extra objects are only referenced from the container, they stay in their original files
+fn add_extra_objects(
+    builder: &mut SerializedFileBuilder<impl TypeTreeProvider>,
+    asset_bundle: &mut AssetBundle,
+    extra_objects: Vec<ExtraObject>,
+) -> Result<()> {
+    let mut meta = MetaIndex::default();
     for (filename, path_id, class_name, object_name) in extra_objects {
-        // TODO cached
-        let file_id = builder.add_external_uncached(FileIdentifier::try_from(filename)?);
+        let file_id = meta.add_external(builder, &FileIdentifier::try_from(filename)?);
         let info = AssetInfo::new(PPtr::new(file_id, path_id));
         asset_bundle
             .m_Container
             .insert(get_extra_object_asset_name(&class_name, &object_name), info);
     }
+    Ok(())
+}
 
+/// Moves the kept objects of all `scenes` into `builder`, and adds their roots to the container
+fn add_asset_scenes(
+    builder: &mut SerializedFileBuilder<impl TypeTreeProvider>,
+    asset_bundle: &mut AssetBundle,
+    scenes: Vec<&mut RepackScene>,
+    tpk: &(impl TypeTreeProvider + Sync),
+    unity_version: &UnityVersion,
+    settings: &PackSettings,
+    stats: &mut Stats,
+) -> Result<()> {
+    let mut path_ids = PathIdAllocator::new(settings.path_ids);
+    let mut meta = MetaIndex::default();
     let intermediate = scenes
         .into_iter()
-        .map(|mut scene| {
+        .map(|scene| {
             let serialized = &mut scene.serialized;
             let data = scene.serialized_data.as_ref();
 
-            assert_eq!(serialized.m_bigIDEnabled, None);
-            if let Some(ref_types) = &serialized.m_RefTypes
-                && !ref_types.is_empty()
-            {
-                warn!(
-                    "Found {} m_RefTypes in scene {}, this is untested.",
-                    ref_types.len(),
-                    scene.scene_name
-                );
-            }
-
             stats.objects_before += serialized.objects().len();
             stats.size_before += data.len();
             serialized.modify_objects(|objects| {
@@ -488,29 +1080,16 @@ pub fn pack_to_asset_bundle(
             });
             stats.objects_after += serialized.objects().len();
 
-            let remap = merge_serialized::add_scene_meta_to_builder(&mut builder, serialized)?;
-
-            if builder.serialized.m_EnableTypeTree {
-                for ty in &mut builder.serialized.m_Types {
-                    if ty.m_Type.is_none() {
-                        if ty.m_ClassID == ClassId::MonoBehaviour {
-                            log::warn!(
-                                "Asset bundle is to be serialized with type tree information, "
-                            );
-                            log::warn!("but the repack sources don't contain any.");
-                            log::warn!(
-                                "Monobehaviours typetrees will not contain serialized fields."
-                            );
-                        }
-                        ty.m_Type = Some(
-                            env.tpk
-                                .get_typetree_node(ty.m_ClassID, unity_version)
-                                .ok_or(serializedfile::Error::NoTypetree(ty.m_ClassID))?
-                                .into_owned(),
-                        );
-                    }
-                }
-            }
+            let remap = merge_serialized::add_scene_meta_to_builder(
+                builder,
+                &mut meta,
+                serialized,
+                serialized.objects(),
+                &mut path_ids,
+                &scene.scene_name,
+                &scene.original_name,
+            )?;
+            add_missing_typetrees(builder, tpk, unity_version)?;
 
             for (scene_path, transform) in scene.roots.iter() {
                 let mut go = transform.m_GameObject;
@@ -529,18 +1108,32 @@ pub fn pack_to_asset_bundle(
         })
         .collect::<Result<Vec<_>>>()?;
 
+    // deduplication has to read the objects of all scenes, so keep their types by new PathId
+    let monobehaviour_types: FxHashMap<PathId, &TypeTreeNode> = match settings.dedupe_objects {
+        true => intermediate
+            .iter()
+            .flat_map(|(scene, remap)| {
+                scene
+                    .monobehaviour_types
+                    .iter()
+                    .filter_map(|(path_id, &tt)| Some((*remap.path_id.get(path_id)?, tt)))
+            })
+            .collect(),
+        false => FxHashMap::default(),
+    };
+
     let objects = intermediate
         .into_par_iter()
-        .map(|(mut scene, remap)| {
+        .map(|(scene, remap)| {
             merge_serialized::remap_objects(
                 &scene.scene_name,
-                scene.original_name,
+                scene.original_name.clone(),
                 &builder.serialized,
                 scene.serialized_data.as_ref(),
-                &env.tpk,
+                tpk,
                 scene.serialized.take_objects(),
-                scene.replacements,
-                scene.monobehaviour_types,
+                std::mem::take(&mut scene.replacements),
+                std::mem::take(&mut scene.monobehaviour_types),
                 remap,
             )
             .collect::<Vec<_>>()
@@ -555,22 +1148,48 @@ pub fn pack_to_asset_bundle(
         Ok(())
     })?;
 
-    builder.add_object_at(1, &asset_bundle)?;
-
-    let mut out = Vec::new();
-    builder.write(&mut Cursor::new(&mut out))?;
-    stats.size_after += out.len();
-
-    let mut bundle_builder = BundleFileBuilder::unityfs(7, unity_version);
-    bundle_builder.add_file(&format!("CAB-{bundle_name}"), Cursor::new(out))?;
+    if settings.dedupe_objects {
+        let (removed_objects, removed_bytes) = identical_objects::merge_identical_objects(
+            builder,
+            asset_bundle,
+            &monobehaviour_types,
+            tpk,
+        )?;
+        stats.objects_after -= removed_objects;
+        stats.deduplicated_bytes += removed_bytes;
+    }
 
-    bundle_builder.write(writer, compression)?;
+    Ok(())
+}
 
-    Ok(stats)
+/// Fills in the typetrees of types taken from files without them, if the builder writes typetrees
+fn add_missing_typetrees(
+    builder: &mut SerializedFileBuilder<impl TypeTreeProvider>,
+    tpk: &impl TypeTreeProvider,
+    unity_version: &UnityVersion,
+) -> Result<()> {
+    if !builder.serialized.m_EnableTypeTree {
+        return Ok(());
+    }
+    for ty in &mut builder.serialized.m_Types {
+        if ty.m_Type.is_none() {
+            if ty.m_ClassID == ClassId::MonoBehaviour {
+                log::warn!("Asset bundle is to be serialized with type tree information, ");
+                log::warn!("but the repack sources don't contain any.");
+                log::warn!("Monobehaviours typetrees will not contain serialized fields.");
+            }
+            ty.m_Type = Some(
+                tpk.get_typetree_node(ty.m_ClassID, unity_version)
+                    .ok_or(serializedfile::Error::NoTypetree(ty.m_ClassID))?
+                    .into_owned(),
+            );
+        }
+    }
+    Ok(())
 }
 
 #[inline(never)]
-fn prepare_monobehaviour_types<'a>(
+pub(crate) fn prepare_monobehaviour_types<'a>(
     env: &'a Environment,
     file: &SerializedFile,
     reader: &mut (impl Read + Seek),
@@ -633,7 +1252,23 @@ pub fn pack_to_shallow_asset_bundle(
         env,
         &repack_settings,
         |filename, scene_name, object_paths, file, data| {
-            let object_paths = deduplicate_objects(filename, scene_name, object_paths);
+            if object_paths
+                .iter()
+                .any(|object| object.is_exclusion() || object.is_whole_scene())
+            {
+                warn!(
+                    "Exclusions and '{}' are not supported for shallow asset bundles, ignoring them in {}",
+                    SceneObject::WHOLE_SCENE,
+                    scene_name_display(scene_name, filename)
+                );
+            }
+            let object_paths = deduplicate_objects(
+                filename,
+                scene_name,
+                object_paths
+                    .iter()
+                    .filter(|object| !object.is_exclusion() && !object.is_whole_scene()),
+            );
 
             objects_before.fetch_add(file.objects().len(), Ordering::Relaxed);
             size_before.fetch_add(data.as_ref().len(), Ordering::Relaxed);
@@ -642,7 +1277,7 @@ pub fn pack_to_shallow_asset_bundle(
 
             let mut path_ids = Vec::with_capacity(object_paths.len());
             let lookup = SceneLookup::new(&file, reader, &env.tpk)?;
-            for path in object_paths {
+            for path in object_paths.into_keys() {
                 let Some((_, transform)) = lookup.lookup_path(reader, path)? else {
                     warn!("Could not find path '{path}' in {scene_name}");
                     continue;
@@ -654,7 +1289,7 @@ pub fn pack_to_shallow_asset_bundle(
         },
     )?;
 
-    create_shallow_assetbundle(
+    let checksum = create_shallow_assetbundle(
         env,
         writer,
         bundle_name,
@@ -668,6 +1303,9 @@ pub fn pack_to_shallow_asset_bundle(
         objects_after: 0,
         size_before: size_before.into_inner(),
         size_after: 0,
+        size_report: None,
+        deduplicated_bytes: 0,
+        checksum,
     })
 }
 
@@ -679,7 +1317,7 @@ fn create_shallow_assetbundle(
     objects: Vec<((String, PathBuf), Vec<(String, PathId)>)>,
     extra_objects: Vec<ExtraObject>,
     compression: CompressionType,
-) -> Result<()> {
+) -> Result<BundleChecksum> {
     let unity_version = env.unity_version()?;
     let common_offset_map = serializedfile::build_common_offset_map(&env.tpk.inner, unity_version);
 
@@ -688,13 +1326,7 @@ fn create_shallow_assetbundle(
 
     let mut ab = AssetBundle::asset_base(bundle_name);
 
-    for (filename, path_id, class_name, object_name) in extra_objects {
-        // TODO cached
-        let file_id = builder.add_external_uncached(FileIdentifier::try_from(filename)?);
-        let info = AssetInfo::new(PPtr::new(file_id, path_id));
-        ab.m_Container
-            .insert(get_extra_object_asset_name(&class_name, &object_name), info);
-    }
+    add_extra_objects(&mut builder, &mut ab, extra_objects)?;
 
     for ((scene_name, original_name), objects) in objects {
         // TODO: is this right?
@@ -712,12 +1344,12 @@ fn create_shallow_assetbundle(
     let mut builder_out = Vec::new();
     builder.write(Cursor::new(&mut builder_out))?;
 
-    let mut bundle = BundleFileBuilder::unityfs(7, unity_version);
-    bundle.add_file(&format!("CAB-{bundle_name}"), Cursor::new(builder_out))?;
-
-    bundle.write(writer, compression)?;
-
-    Ok(())
+    checksum::write_unityfs(
+        writer,
+        unity_version,
+        vec![(format!("CAB-{bundle_name}"), builder_out)],
+        compression,
+    )
 }
 
 fn get_scene_bundle_filename(bundle_name: &str, scene_name: &str) -> String {
@@ -733,3 +1365,296 @@ fn get_asset_bundle_object_asset_name(scene_name: &str, scene_path: &str) -> Str
 fn get_extra_object_asset_name(class_name: &str, object_name: &str) -> String {
     format!("ExtraObjects/{class_name}/{object_name}.prefab").to_lowercase()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{self, TestScene, Tpk};
+
+    fn test_scenes(tpk: &Tpk) -> Vec<RepackScene<'static>> {
+        let mut first = TestScene::new("first");
+        let material = first.add_material(tpk, "Shared");
+        first.add_renderer(tpk, "A", material);
+        first.add_renderer(tpk, "B", material);
+
+        let mut second = TestScene::new("second");
+        let material = second.add_material(tpk, "Other");
+        second.add_renderer(tpk, "C", material);
+
+        vec![first.repack(tpk), second.repack(tpk)]
+    }
+
+    /// Packs the test scenes on one and on several threads, the output has to be the same
+    fn assert_deterministic(pack: impl Fn(&Tpk, Vec<RepackScene>) -> Vec<u8> + Sync) {
+        let tpk = test_utils::tpk();
+        let [single, multi] = [1, 4].map(|num_threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(num_threads)
+                .build()
+                .unwrap()
+                .install(|| pack(&tpk, test_scenes(&tpk)))
+        });
+        assert!(single == multi, "output depends on the number of threads");
+    }
+
+    fn pack_scenes(tpk: &Tpk, mut scenes: Vec<RepackScene>, settings: &PackSettings) -> Vec<u8> {
+        let mut out = Cursor::new(Vec::new());
+        pack_to_scene_bundle(
+            &mut out,
+            "test",
+            &tpk.inner,
+            tpk,
+            &test_utils::unity_version(),
+            &mut scenes,
+            Vec::new(),
+            CompressionType::None,
+            settings,
+        )
+        .unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn scene_bundle_is_deterministic() {
+        assert_deterministic(|tpk, scenes| pack_scenes(tpk, scenes, &PackSettings::default()));
+    }
+
+    #[test]
+    fn merged_scene_is_deterministic() {
+        let settings = PackSettings {
+            scene_groups: IndexMap::from([(
+                "merged".to_owned(),
+                vec!["first".to_owned(), "second".to_owned()],
+            )]),
+            ..Default::default()
+        };
+        assert_deterministic(|tpk, scenes| pack_scenes(tpk, scenes, &settings));
+    }
+
+    /// Repacks a game with extra objects through the file listing, with the files created and the
+    /// extra objects requested in the given order
+    fn pack_game_with_extra_objects(name: &str, reverse: bool, num_threads: usize) -> Vec<u8> {
+        let tpk = test_utils::tpk();
+
+        let mut first = TestScene::new("first");
+        let material = first.add_material(&tpk, "Shared");
+        first.add_renderer(&tpk, "A", material);
+        first.add_script_object(&tpk, "Config", "Level");
+        let mut second = TestScene::new("second");
+        let material = second.add_material(&tpk, "Other");
+        second.add_renderer(&tpk, "C", material);
+
+        let mut resources = TestScene::new("resources.assets");
+        resources.add_script_object(&tpk, "Config", "Global");
+        resources.add_script_object(&tpk, "Enemy", "Crawler");
+        resources.add_script_object(&tpk, "Enemy", "Unused");
+
+        let game = test_utils::TestGame::new(&tpk, name, &[first, second], &[resources], reverse);
+        let env = game.env();
+
+        let mut extra_objects = IndexMap::from([
+            (
+                "Config".to_owned(),
+                IndexSet::from(["Global".to_owned(), "Level".to_owned()]),
+            ),
+            ("Enemy".to_owned(), IndexSet::from(["Crawler".to_owned()])),
+        ]);
+        if reverse {
+            extra_objects.reverse();
+            extra_objects.values_mut().for_each(IndexSet::reverse);
+        }
+        let repack_settings = RepackSettings {
+            scene_objects: IndexMap::from([
+                ("first".to_owned(), vec![SceneObject::Path("A".to_owned())]),
+                ("second".to_owned(), vec![SceneObject::Path("C".to_owned())]),
+            ]),
+            extra_objects,
+            scene_targets: IndexMap::new(),
+        };
+
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build()
+            .unwrap()
+            .install(|| {
+                let (scenes, extra_objects) = repack_scenes(
+                    &env,
+                    repack_settings,
+                    true,
+                    false,
+                    RootTransforms::default(),
+                    false,
+                )
+                .unwrap();
+                assert_eq!(extra_objects.len(), 3);
+
+                let mut out = Cursor::new(Vec::new());
+                pack_to_asset_bundle(
+                    &env,
+                    &mut out,
+                    "test",
+                    &tpk.inner,
+                    scenes,
+                    extra_objects,
+                    CompressionType::None,
+                    false,
+                    &PackSettings::default(),
+                )
+                .unwrap();
+                out.into_inner()
+            })
+    }
+
+    #[test]
+    fn asset_bundle_with_extra_objects_is_deterministic() {
+        let expected = pack_game_with_extra_objects("extra-objects-in-order", false, 1);
+        let shuffled = pack_game_with_extra_objects("extra-objects-reversed", true, 4);
+        assert!(
+            expected == shuffled,
+            "output depends on the order of the game files or extra objects"
+        );
+    }
+
+    /// The PathIds of all objects of an asset bundle, and of the requested object `root`
+    fn packed_path_ids(tpk: &Tpk, mut scene: RepackScene, root: &str) -> (Vec<PathId>, PathId) {
+        let settings = PackSettings {
+            path_ids: PathIdStrategy::Stable,
+            ..Default::default()
+        };
+        let mut out = Cursor::new(Vec::new());
+        pack_asset_bundle(
+            &mut out,
+            "test",
+            &tpk.inner,
+            tpk,
+            &test_utils::unity_version(),
+            vec![&mut scene],
+            Vec::new(),
+            CompressionType::None,
+            false,
+            &settings,
+        )
+        .unwrap();
+
+        let entries = inspect::read_bundle(out.get_ref()).unwrap();
+        let file = entries[0].serialized.as_ref().unwrap();
+        let path_ids = file.objects().map(|info| info.m_PathID).collect();
+        let asset_bundle = inspect::inspect_bundle(&entries, tpk)
+            .unwrap()
+            .asset_bundle
+            .unwrap();
+        let path = get_asset_bundle_object_asset_name(&scene.scene_name, root);
+        let root = asset_bundle
+            .container
+            .iter()
+            .find(|entry| entry.path == path)
+            .unwrap_or_else(|| panic!("'{path}' is not in the container"));
+        (path_ids, root.path_id)
+    }
+
+    #[test]
+    fn stable_path_ids_survive_a_larger_selection() {
+        let tpk = test_utils::tpk();
+        let scene = |roots: &[&str]| {
+            let mut scene = TestScene::new("first");
+            let material = scene.add_material(&tpk, "Shared");
+            for root in roots {
+                scene.add_renderer(&tpk, root, material);
+            }
+            scene.repack(&tpk)
+        };
+
+        let (subset, subset_root) = packed_path_ids(&tpk, scene(&["A"]), "A");
+        let (superset, superset_root) = packed_path_ids(&tpk, scene(&["A", "B"]), "A");
+
+        assert_eq!(subset_root, superset_root);
+        assert!(superset.len() > subset.len());
+        for path_id in subset {
+            assert!(
+                superset.contains(&path_id),
+                "object {path_id} got a new PathId when adding another root"
+            );
+        }
+    }
+
+    #[test]
+    fn scene_bundle_lists_extra_objects() {
+        let tpk = test_utils::tpk();
+        let mut scenes = test_scenes(&tpk);
+        let extra_objects = vec![(
+            PathBuf::from("resources.assets"),
+            5,
+            "MonoBehaviour".to_owned(),
+            "Config".to_owned(),
+        )];
+
+        let mut out = Cursor::new(Vec::new());
+        pack_to_scene_bundle(
+            &mut out,
+            "test",
+            &tpk.inner,
+            &tpk,
+            &test_utils::unity_version(),
+            &mut scenes,
+            extra_objects,
+            CompressionType::None,
+            &PackSettings::default(),
+        )
+        .unwrap();
+
+        let entries = inspect::read_bundle(out.get_ref()).unwrap();
+        let info = inspect::inspect_bundle(&entries, &tpk).unwrap();
+        let assets = info
+            .files
+            .iter()
+            .find(|file| file.name == "CAB-test")
+            .expect("no asset file in the bundle");
+        assert_eq!(
+            assets.externals.as_deref(),
+            Some(&["resources.assets".to_owned()][..])
+        );
+
+        let asset_bundle = info.asset_bundle.unwrap();
+        assert_eq!(asset_bundle.scenes.len(), 2);
+        let assets = asset_bundle
+            .container
+            .iter()
+            .filter(|entry| !entry.path.ends_with(".unity"))
+            .collect::<Vec<_>>();
+        let [entry] = assets.as_slice() else {
+            panic!("expected one asset in the container, got {assets:?}");
+        };
+        assert_eq!(entry.path, "extraobjects/monobehaviour/config.prefab");
+        assert_eq!((entry.file_id, entry.path_id), (1, 5));
+    }
+
+    #[test]
+    fn multi_bundle_is_deterministic() {
+        assert_deterministic(|tpk, scenes| {
+            let multi = pack_to_multi_asset_bundle(
+                "test",
+                &tpk.inner,
+                tpk,
+                &test_utils::unity_version(),
+                scenes,
+                Vec::new(),
+                &BundleSplit::PerRoot,
+                CompressionType::None,
+                false,
+                &PackSettings::default(),
+            )
+            .unwrap();
+            assert!(
+                multi.bundles.len() > 1,
+                "the shared material gets its own bundle"
+            );
+
+            multi
+                .bundles
+                .into_iter()
+                .chain([multi.manifest])
+                .flat_map(|bundle| bundle.data)
+                .collect()
+        });
+    }
+}
@@ -0,0 +1,104 @@
+//! Writing the `AssetBundleManifest` bundle, which lists a set of bundles and their dependencies.
+use anyhow::{Context, Result};
+use indexmap::IndexMap;
+use rabex::UnityVersion;
+use rabex::files::bundlefile::CompressionType;
+use rabex::files::serializedfile;
+use rabex::files::serializedfile::builder::SerializedFileBuilder;
+use rabex::objects::pptr::PPtr;
+use rabex::tpk::TpkTypeTreeBlob;
+use rabex::typetree::TypeTreeProvider;
+use rabex_env::unity::types::{AssetBundle, AssetInfo};
+use rustc_hash::FxHashMap;
+use std::io::{Cursor, Seek, Write};
+
+use crate::checksum::{self, BundleChecksum};
+use crate::unity_types::{AssetBundleInfo, AssetBundleManifest};
+
+pub use crate::unity_types::Hash128;
+
+/// Container path of the manifest, as used by Unity
+const MANIFEST_ASSET_NAME: &str = "assetbundlemanifest";
+
+/// A bundle listed in the manifest
+#[derive(Debug, Clone)]
+pub struct ManifestEntry {
+    /// Name the bundle is loaded by, usually its file name
+    pub name: String,
+    pub hash: Hash128,
+    /// Names of other entries that have to be loaded before this one
+    pub dependencies: Vec<String>,
+}
+
+/// Writes a bundle named `manifest_name` containing the `AssetBundleManifest` for `bundles`,
+/// loadable using `LoadAsset<AssetBundleManifest>("AssetBundleManifest")`.
+///
+/// Dependencies have to be entries of `bundles` as well.
+pub fn pack_manifest_bundle(
+    writer: impl Write + Seek,
+    manifest_name: &str,
+    tpk_blob: &TpkTypeTreeBlob,
+    tpk: &impl TypeTreeProvider,
+    unity_version: &UnityVersion,
+    bundles: &[ManifestEntry],
+    compression: CompressionType,
+) -> Result<BundleChecksum> {
+    let indices = bundles
+        .iter()
+        .enumerate()
+        .map(|(i, bundle)| (bundle.name.as_str(), i as i32))
+        .collect::<FxHashMap<_, _>>();
+
+    let mut manifest = AssetBundleManifest {
+        m_Name: "AssetBundleManifest".to_owned(),
+        AssetBundleNames: IndexMap::new(),
+        AssetBundlesWithVariant: Vec::new(),
+        AssetBundleInfos: IndexMap::new(),
+    };
+    for (i, bundle) in bundles.iter().enumerate() {
+        let dependencies = bundle
+            .dependencies
+            .iter()
+            .map(|dependency| {
+                indices.get(dependency.as_str()).copied().with_context(|| {
+                    format!(
+                        "Bundle '{}' depends on '{dependency}', which is not part of the manifest",
+                        bundle.name
+                    )
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        manifest
+            .AssetBundleNames
+            .insert(i as i32, bundle.name.clone());
+        manifest.AssetBundleInfos.insert(
+            i as i32,
+            AssetBundleInfo {
+                AssetBundleHash: bundle.hash,
+                AssetBundleDependencies: dependencies,
+            },
+        );
+    }
+
+    let common_offset_map = serializedfile::build_common_offset_map(tpk_blob, unity_version);
+    let mut builder = SerializedFileBuilder::new(unity_version, tpk, &common_offset_map, true);
+
+    let mut asset_bundle = AssetBundle::asset_base(manifest_name);
+    asset_bundle.m_Container.insert(
+        MANIFEST_ASSET_NAME.to_owned(),
+        AssetInfo::new(PPtr::local(2)),
+    );
+    builder.add_object_at(1, &asset_bundle)?;
+    builder.add_object_at(2, &manifest)?;
+
+    let mut out = Vec::new();
+    builder.write(&mut Cursor::new(&mut out))?;
+
+    checksum::write_unityfs(
+        writer,
+        unity_version,
+        vec![(format!("CAB-{manifest_name}"), out)],
+        compression,
+    )
+}
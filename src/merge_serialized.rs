@@ -1,79 +1,266 @@
-use anyhow::{Context, Result};
-use log::warn;
+use anyhow::{Context, Result, ensure};
 use rabex::files::SerializedFile;
-use rabex::files::serializedfile::ObjectInfo;
 use rabex::files::serializedfile::builder::SerializedFileBuilder;
+use rabex::files::serializedfile::{
+    FileIdentifier, LocalSerializedObjectIdentifier, ObjectInfo, SerializedType,
+};
 use rabex::objects::pptr::{FileId, PathId};
 use rabex::typetree::{TypeTreeNode, TypeTreeProvider};
 use rabex_env::trace_pptr::replace_pptrs_inplace_endianed;
 use rustc_hash::{FxHashMap, FxHashSet};
 use std::borrow::Cow;
-use std::fmt::Debug;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use crate::path_ids::PathIdAllocator;
+use crate::references::{ManagedTypes, managed_type_key, rewrite_pptrs};
 use crate::scene_name_display;
 
 pub struct RemapSerializedIndices {
     pub path_id: FxHashMap<PathId, PathId>,
     pub file_id: FxHashMap<FileId, FileId>,
+    /// The same as `file_id` by externals index, for rewriting the data of managed references
+    externals: FxHashMap<usize, usize>,
     pub types: FxHashMap<i32, i32>,
 }
 
-/// Takes the metadata (types, externals etc.) from `file` and moves them into `builder`.
-pub fn add_scene_meta_to_builder(
-    builder: &mut SerializedFileBuilder<impl TypeTreeProvider>,
-    file: &mut SerializedFile,
-) -> Result<RemapSerializedIndices> {
-    if let Some(ref_types) = &file.m_RefTypes
-        && !ref_types.is_empty()
-    {
-        warn!(
-            "Found {} m_RefTypes in scene, this is untested.",
-            ref_types.len(),
+impl RemapSerializedIndices {
+    /// Points the external `index` of the original file at the external `new_index` of the builder
+    pub fn insert_external(&mut self, index: usize, new_index: usize) {
+        self.file_id.insert(
+            FileId::from_externals_index(index),
+            FileId::from_externals_index(new_index),
         );
+        self.externals.insert(index, new_index);
+    }
+}
+
+/// Where the externals and script types of a builder are, so that files referencing the same ones
+/// share a single entry. Entries that were added to the builder directly are picked up as well.
+#[derive(Default)]
+pub struct MetaIndex {
+    /// Externals by path, the guid is compared on lookup
+    externals: FxHashMap<String, Vec<usize>>,
+    indexed_externals: usize,
+    /// Script types by file id and PathId of their `MonoScript`, only for scripts in externals.
+    /// Local PathIds of different files may refer to different scripts.
+    script_types: FxHashMap<(FileId, PathId), usize>,
+    indexed_script_types: usize,
+    /// Types of managed references by class, namespace and assembly
+    ref_types: FxHashMap<(String, String, String), usize>,
+    indexed_ref_types: usize,
+}
+
+impl MetaIndex {
+    fn sync(&mut self, file: &SerializedFile) {
+        for (index, external) in file
+            .m_Externals
+            .iter()
+            .enumerate()
+            .skip(self.indexed_externals)
+        {
+            let indices = self.externals.entry(external.pathName.clone()).or_default();
+            indices.push(index);
+        }
+        self.indexed_externals = file.m_Externals.len();
+
+        let script_types = file.m_ScriptTypes.as_deref().unwrap_or_default();
+        for (index, ty) in script_types
+            .iter()
+            .enumerate()
+            .skip(self.indexed_script_types)
+        {
+            if ty.m_LocalSerializedFileIndex == 0 {
+                continue;
+            }
+            let key = (ty.m_LocalSerializedFileIndex, ty.m_LocalIdentifierInFile);
+            self.script_types.entry(key).or_insert(index);
+        }
+        self.indexed_script_types = script_types.len();
+
+        let ref_types = file.m_RefTypes.as_deref().unwrap_or_default();
+        for (index, ty) in ref_types.iter().enumerate().skip(self.indexed_ref_types) {
+            self.ref_types.entry(owned_key(ty)).or_insert(index);
+        }
+        self.indexed_ref_types = ref_types.len();
+    }
+
+    /// Adds `external` to the externals of `builder`, unless a file with the same path and guid
+    /// is already referenced
+    pub fn add_external(
+        &mut self,
+        builder: &mut SerializedFileBuilder<impl TypeTreeProvider>,
+        external: &FileIdentifier,
+    ) -> FileId {
+        FileId::from_externals_index(self.add_external_index(builder, external))
+    }
+
+    /// Like [`MetaIndex::add_external`], returning the index into the externals
+    pub fn add_external_index(
+        &mut self,
+        builder: &mut SerializedFileBuilder<impl TypeTreeProvider>,
+        external: &FileIdentifier,
+    ) -> usize {
+        self.sync(&builder.serialized);
+
+        let externals = &mut builder.serialized.m_Externals;
+        let indices = self.externals.entry(external.pathName.clone()).or_default();
+        match indices
+            .iter()
+            .copied()
+            .find(|&index| externals[index].guid == external.guid)
+        {
+            Some(index) => index,
+            None => {
+                externals.push(external.clone());
+                indices.push(externals.len() - 1);
+                self.indexed_externals = externals.len();
+                externals.len() - 1
+            }
+        }
+    }
+
+    /// Adds `ty` to the script types of `builder`, unless one for the same script in an external
+    /// is already there
+    fn add_script_type(
+        &mut self,
+        builder: &mut SerializedFileBuilder<impl TypeTreeProvider>,
+        ty: &LocalSerializedObjectIdentifier,
+    ) -> usize {
+        self.sync(&builder.serialized);
+
+        let script_types = builder.serialized.m_ScriptTypes.get_or_insert_default();
+        if ty.m_LocalSerializedFileIndex == 0 {
+            script_types.push(ty.clone());
+            self.indexed_script_types = script_types.len();
+            return script_types.len() - 1;
+        }
+        let key = (ty.m_LocalSerializedFileIndex, ty.m_LocalIdentifierInFile);
+        *self.script_types.entry(key).or_insert_with(|| {
+            script_types.push(ty.clone());
+            self.indexed_script_types = script_types.len();
+            script_types.len() - 1
+        })
     }
 
+    /// Adds `ty` to the types of managed references of `builder`, unless the same class is
+    /// already there
+    fn add_ref_type(
+        &mut self,
+        builder: &mut SerializedFileBuilder<impl TypeTreeProvider>,
+        ty: &SerializedType,
+    ) -> usize {
+        self.sync(&builder.serialized);
+
+        let ref_types = builder.serialized.m_RefTypes.get_or_insert_default();
+        *self.ref_types.entry(owned_key(ty)).or_insert_with(|| {
+            ref_types.push(ty.clone());
+            self.indexed_ref_types = ref_types.len();
+            ref_types.len() - 1
+        })
+    }
+}
+
+fn owned_key(ty: &SerializedType) -> (String, String, String) {
+    let (class, namespace, assembly) = managed_type_key(ty);
+    (class.to_owned(), namespace.to_owned(), assembly.to_owned())
+}
+
+/// Copies the metadata (types, externals etc.) used by `objects` of `file` into `builder`,
+/// and assigns new PathIds to the objects using `path_ids`.
+pub fn add_scene_meta_to_builder<'a>(
+    builder: &mut SerializedFileBuilder<impl TypeTreeProvider>,
+    meta: &mut MetaIndex,
+    file: &SerializedFile,
+    objects: impl Iterator<Item = &'a ObjectInfo> + Clone,
+    path_ids: &mut PathIdAllocator,
+    scene_name: &str,
+    original_name: &Path,
+) -> Result<RemapSerializedIndices> {
+    // scene bundles copy these files as they are, merging would have to change the PathId width
+    ensure!(
+        !matches!(file.m_bigIDEnabled, Some(enabled) if enabled != 0),
+        "{} uses 64-bit PathIds in the old serialized file format (m_bigIDEnabled), which cannot be merged into another file yet, only packed as a scene bundle of its own",
+        scene_name_display(scene_name, original_name)
+    );
+
+    let used_types: FxHashSet<_> = objects.clone().map(|obj| obj.m_TypeID).collect();
+
     let mut remap_path_id = FxHashMap::default();
-    for obj in file.objects() {
-        remap_path_id.insert(obj.m_PathID, builder.get_next_path_id());
+    for obj in objects {
+        let new_path_id = path_ids.next(builder, scene_name, original_name, obj.m_PathID);
+        remap_path_id.insert(obj.m_PathID, new_path_id);
     }
 
-    let mut remap_file_id = FxHashMap::default();
-    // TODO: deduplicate
+    let mut remap = RemapSerializedIndices {
+        path_id: remap_path_id,
+        file_id: FxHashMap::default(),
+        externals: FxHashMap::default(),
+        types: FxHashMap::default(),
+    };
     for (i, external) in file.m_Externals.iter().enumerate() {
-        let orig_file_id = FileId::from_externals_index(i);
-        let new_file_id = FileId::from_externals_index(builder.serialized.m_Externals.len());
-        remap_file_id.insert(orig_file_id, new_file_id);
-        builder.serialized.m_Externals.push(external.clone());
-    }
-    for ty in file.m_ScriptTypes.as_deref_mut().unwrap_or_default() {
-        ty.m_LocalSerializedFileIndex = *remap_file_id
-            // .get(&(ty.m_LocalIdentifierInFile as i32)) TODO: this was previously here??
-            .get(&ty.m_LocalSerializedFileIndex)
-            .unwrap_or(&ty.m_LocalSerializedFileIndex);
-    }
-    // TODO: deduplicate
-    let remap_script_types = remap_vecs_all::<i16, _>(
-        file.m_ScriptTypes.as_mut().unwrap_or(&mut vec![]),
-        builder.serialized.m_ScriptTypes.get_or_insert_default(),
-    );
-    for ty in &mut file.m_Types {
+        let new_index = meta.add_external_index(builder, external);
+        remap.insert_external(i, new_index);
+    }
+
+    let mut remap_script_types = FxHashMap::default();
+    for (i, ty) in file.m_ScriptTypes.iter().flatten().enumerate() {
+        let mut ty = ty.clone();
+        if ty.m_LocalSerializedFileIndex == 0 {
+            // the script moves into `builder` together with the other objects
+            ty.m_LocalIdentifierInFile = *remap
+                .path_id
+                .get(&ty.m_LocalIdentifierInFile)
+                .unwrap_or(&ty.m_LocalIdentifierInFile);
+        } else {
+            ty.m_LocalSerializedFileIndex = *remap
+                .file_id
+                .get(&ty.m_LocalSerializedFileIndex)
+                .unwrap_or(&ty.m_LocalSerializedFileIndex);
+        }
+
+        let new_index = meta.add_script_type(builder, &ty);
+        remap_script_types.insert(i as i16, new_index as i16);
+    }
+
+    // managed references name their type, so only the dependencies of the types are renumbered
+    let ref_types = file.m_RefTypes.as_deref().unwrap_or_default();
+    let mut remap_ref_types = FxHashMap::default();
+    for (i, ty) in file.m_Types.iter().enumerate() {
+        if !used_types.contains(&(i as i32)) {
+            continue;
+        }
+        let mut ty = ty.clone();
         ty.m_ScriptTypeIndex = *remap_script_types
             .get(&ty.m_ScriptTypeIndex)
             .unwrap_or(&ty.m_ScriptTypeIndex);
+        for dependency in ty.m_TypeDependencies.iter_mut().flatten() {
+            let new_index = match remap_ref_types.get(dependency) {
+                Some(&new_index) => new_index,
+                None => {
+                    let ref_type = usize::try_from(*dependency)
+                        .ok()
+                        .and_then(|index| ref_types.get(index))
+                        .with_context(|| {
+                            format!(
+                                "Type {i} of {} depends on the missing managed reference type {dependency}",
+                                scene_name_display(scene_name, original_name)
+                            )
+                        })?;
+                    let new_index = meta.add_ref_type(builder, ref_type) as i32;
+                    remap_ref_types.insert(*dependency, new_index);
+                    new_index
+                }
+            };
+            *dependency = new_index;
+        }
+
+        remap
+            .types
+            .insert(i as i32, builder.serialized.m_Types.len() as i32);
+        builder.serialized.m_Types.push(ty);
     }
-    let used_types: FxHashSet<_> = file.objects().map(|obj| obj.m_TypeID).collect();
-    let remap_types = remap_vecs(
-        used_types,
-        &mut file.m_Types,
-        &mut builder.serialized.m_Types,
-    );
 
-    Ok(RemapSerializedIndices {
-        path_id: remap_path_id,
-        file_id: remap_file_id,
-        types: remap_types,
-    })
+    Ok(remap)
 }
 
 pub fn remap_objects(
@@ -87,7 +274,14 @@ pub fn remap_objects(
     mb_types: FxHashMap<PathId, &TypeTreeNode>,
     remap: RemapSerializedIndices,
 ) -> impl Iterator<Item = Result<(ObjectInfo, Cow<'static, [u8]>)>> {
+    let managed = ManagedTypes::of(file);
     objects.into_iter().map(move |mut obj| -> Result<_> {
+        let has_managed_references = file.m_Types[obj.m_TypeID as usize]
+            .m_TypeDependencies
+            .iter()
+            .flatten()
+            .next()
+            .is_some();
         obj.m_TypeID = remap.types[&obj.m_TypeID];
 
         let tt = match mb_types.get(&obj.m_PathID) {
@@ -107,13 +301,31 @@ pub fn remap_objects(
         let orig_path_id = obj.m_PathID;
         obj.m_PathID = *remap.path_id.get(&obj.m_PathID).unwrap_or(&obj.m_PathID);
 
-        replace_pptrs_inplace_endianed(
-            object_data.to_mut().as_mut_slice(),
-            tt,
-            &remap.path_id,
-            &remap.file_id,
-            file.m_Header.m_Endianess,
-        )
+        let endianness = file.m_Header.m_Endianess;
+        let data = object_data.to_mut().as_mut_slice();
+        match has_managed_references {
+            // the typetree of the object does not describe the data of its managed references
+            true => rewrite_pptrs(
+                data,
+                tt,
+                endianness,
+                &managed,
+                |file_id, path_id| match file_id {
+                    0 => remap.path_id.get(&path_id).map(|&path_id| (0, path_id)),
+                    _ => {
+                        let index = usize::try_from(file_id - 1).ok()?;
+                        let new_index = remap.externals.get(&index)?;
+                        Some((*new_index as i32 + 1, path_id))
+                    }
+                },
+            )
+            .map(drop),
+            false => {
+                replace_pptrs_inplace_endianed(data, tt, &remap.path_id, &remap.file_id, endianness)
+                    .map(drop)
+                    .map_err(anyhow::Error::from)
+            }
+        }
         .with_context(|| {
             format!(
                 "Could not remap path IDs in bundle for {orig_path_id} in {}:\n{}",
@@ -126,41 +338,284 @@ pub fn remap_objects(
     })
 }
 
-/// Moves the elements from `old` into `new`, returning where each index ended up in
-#[must_use]
-fn remap_vecs_all<I, T>(old: &mut Vec<T>, new: &mut Vec<T>) -> FxHashMap<I, I>
-where
-    I: std::hash::Hash + std::cmp::Eq + TryFrom<usize>,
-    <I as TryFrom<usize>>::Error: Debug,
-{
-    std::mem::take(old)
-        .into_iter()
-        .enumerate()
-        .map(|(old_index, ty)| {
-            let new_index = new.len();
-            new.push(ty);
-            (old_index.try_into().unwrap(), new_index.try_into().unwrap())
-        })
-        .filter(|(old, new)| old != new)
-        .collect()
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path_ids::PathIdStrategy;
+    use crate::references::object_references;
+    use crate::test_utils::{self, TestScene, Tpk};
+    use crate::{RepackScene, objects};
+    use rabex::files::serializedfile;
+    use rabex::objects::ClassId;
+
+    /// Adds the metadata of all objects of `scenes` to one builder, like merging them would
+    fn add_scenes_meta(
+        tpk: &Tpk,
+        scenes: &[RepackScene],
+    ) -> (SerializedFile, Vec<RemapSerializedIndices>) {
+        let unity_version = test_utils::unity_version();
+        let common_offset_map = serializedfile::build_common_offset_map(&tpk.inner, &unity_version);
+        let mut builder =
+            SerializedFileBuilder::new(&unity_version, tpk, &common_offset_map, false);
+        let mut meta = MetaIndex::default();
+        let mut path_ids = PathIdAllocator::new(PathIdStrategy::Sequential);
+        let remaps = scenes
+            .iter()
+            .map(|scene| {
+                add_scene_meta_to_builder(
+                    &mut builder,
+                    &mut meta,
+                    &scene.serialized,
+                    scene.serialized.objects(),
+                    &mut path_ids,
+                    &scene.scene_name,
+                    &scene.original_name,
+                )
+                .unwrap()
+            })
+            .collect();
+        (builder.serialized, remaps)
+    }
+
+    #[test]
+    fn externals_are_added_once() {
+        let tpk = test_utils::tpk();
+        let scenes = ["first", "second"].map(|name| {
+            let mut scene = TestScene::new(name);
+            scene.add_external("sharedassets0.assets");
+            scene.add_external("sharedassets1.assets");
+            let material = scene.add_material(&tpk, "Material");
+            scene.add_renderer(&tpk, "Root", material);
+            scene.repack(&tpk)
+        });
+
+        let (merged, remaps) = add_scenes_meta(&tpk, &scenes);
+
+        let externals = &merged.m_Externals;
+        assert_eq!(externals.len(), 2);
+        assert_ne!(externals[0].pathName, externals[1].pathName);
+        assert_eq!(remaps[0].file_id, remaps[1].file_id);
+    }
+
+    #[test]
+    fn only_external_script_types_are_shared() {
+        let tpk = test_utils::tpk();
+        let external_script = 11;
+        let scenes = ["first", "second"].map(|name| {
+            let mut scene = TestScene::new(name);
+            scene.add_external("globalgamemanagers.assets");
+            let material = scene.add_material(&tpk, "Material");
+            scene.add_renderer(&tpk, "Root", material);
+            let mut scene = scene.repack(&tpk);
+            let local = scene.roots[0].1.m_GameObject.m_FileID;
+            // both files use the same PathId for a script of their own
+            scene.serialized.m_ScriptTypes = Some(vec![
+                LocalSerializedObjectIdentifier {
+                    m_LocalSerializedFileIndex: FileId::from_externals_index(0),
+                    m_LocalIdentifierInFile: external_script,
+                },
+                LocalSerializedObjectIdentifier {
+                    m_LocalSerializedFileIndex: local,
+                    m_LocalIdentifierInFile: material,
+                },
+            ]);
+            scene
+        });
+
+        let (merged, remaps) = add_scenes_meta(&tpk, &scenes);
+
+        let local = scenes[0].roots[0].1.m_GameObject.m_FileID;
+        let script_types = merged
+            .m_ScriptTypes
+            .unwrap()
+            .into_iter()
+            .map(|ty| (ty.m_LocalSerializedFileIndex, ty.m_LocalIdentifierInFile))
+            .collect::<Vec<_>>();
+        let material = 1;
+        assert_eq!(
+            script_types,
+            [
+                (FileId::from_externals_index(0), external_script),
+                (local, remaps[0].path_id[&material]),
+                (local, remaps[1].path_id[&material]),
+            ]
+        );
+        assert_ne!(remaps[0].path_id[&material], remaps[1].path_id[&material]);
+    }
+
+    /// The field `name` of the typetree of `class_id`, renamed to `new_name`
+    fn renamed_field(tpk: &Tpk, class_id: ClassId, name: &str, new_name: &str) -> TypeTreeNode {
+        let mut field = test_utils::field(tpk, class_id, name);
+        field.m_Name = new_name.to_owned();
+        field
+    }
+
+    fn node(
+        template: &TypeTreeNode,
+        ty: &str,
+        name: &str,
+        children: Vec<TypeTreeNode>,
+    ) -> TypeTreeNode {
+        let mut node = template.clone();
+        node.m_Type = ty.to_owned();
+        node.m_Name = name.to_owned();
+        node.m_MetaFlag = Some(0);
+        node.children = children;
+        node
+    }
 
-#[must_use]
-fn remap_vecs<T>(
-    used_types: FxHashSet<i32>,
-    old: &mut Vec<T>,
-    new: &mut Vec<T>,
-) -> FxHashMap<i32, i32> {
-    let mut old_to_new: FxHashMap<i32, i32> = FxHashMap::default();
-
-    for (old_index, ty) in std::mem::take(old)
-        .into_iter()
-        .enumerate()
-        .filter(|&(idx, _)| used_types.contains(&(idx as i32)))
-    {
-        let new_index = new.len();
-        old_to_new.insert(old_index as i32, new_index as i32);
-        new.push(ty);
-    }
-    old_to_new
+    fn string_data(value: &str) -> Vec<u8> {
+        let mut data = (value.len() as i32).to_le_bytes().to_vec();
+        data.extend(value.as_bytes());
+        data.resize(data.len().next_multiple_of(4), 0);
+        data
+    }
+
+    /// A scene whose `MeshRenderer` has a managed reference of type `Payload`, which points at
+    /// the material. With `other_type_first`, an unused type comes first in `m_RefTypes`.
+    fn managed_reference_scene(
+        tpk: &Tpk,
+        name: &str,
+        other_type_first: bool,
+    ) -> RepackScene<'static> {
+        let mut scene = TestScene::new(name);
+        let material = scene.add_material(tpk, "Material");
+        let renderer = scene.add_renderer(tpk, "Root", material);
+        let mut scene = scene.repack(tpk);
+
+        // typetrees of the registry, built from fields of the same layout
+        let pptr = renamed_field(tpk, ClassId::MeshRenderer, "m_GameObject", "target");
+        let int = node(
+            &renamed_field(tpk, ClassId::GameObject, "m_Layer", "version"),
+            "int",
+            "version",
+            Vec::new(),
+        );
+        let mut string = renamed_field(tpk, ClassId::GameObject, "m_Name", "class");
+        string.m_MetaFlag = Some(0x4000);
+        let strings = ["class", "ns", "asm"].map(|name| {
+            let mut string = string.clone();
+            string.m_Name = name.to_owned();
+            string
+        });
+        let referenced_object = node(
+            &pptr,
+            "ReferencedObject",
+            "data",
+            vec![
+                node(&pptr.children[1], "SInt64", "rid", Vec::new()),
+                node(&pptr, "ReferencedManagedType", "type", strings.to_vec()),
+                node(&int, "ReferencedObjectData", "data", Vec::new()),
+            ],
+        );
+        let mut ref_ids = renamed_field(tpk, ClassId::GameObject, "m_Component", "RefIds");
+        ref_ids.children[0].children[1] = referenced_object;
+        let registry = node(
+            &pptr,
+            "ManagedReferencesRegistry",
+            "references",
+            vec![int, ref_ids],
+        );
+
+        let info = scene
+            .serialized
+            .objects()
+            .find(|info| info.m_PathID == renderer)
+            .unwrap()
+            .clone();
+        let ty = &mut scene.serialized.m_Types[info.m_TypeID as usize];
+        ty.m_Type.as_mut().unwrap().children.push(registry);
+
+        let mut payload = ty.clone();
+        payload.m_Type = Some(node(&pptr, "Payload", "Base", vec![pptr.clone()]));
+        payload.m_TypeDependencies = None;
+        payload.m_KlassName = Some("Payload".to_owned());
+        payload.m_NameSpace = Some("Game".to_owned());
+        payload.m_AsmName = Some("Assembly-CSharp".to_owned());
+        let mut other = payload.clone();
+        other.m_KlassName = Some("Other".to_owned());
+
+        let ref_types = match other_type_first {
+            true => vec![other, payload],
+            false => vec![payload],
+        };
+        ty.m_TypeDependencies = Some(vec![ref_types.len() as i32 - 1]);
+        scene.serialized.m_RefTypes = Some(ref_types);
+
+        let mut data = objects::object_data(scene.serialized_data.as_ref(), &info).to_vec();
+        data.extend(2i32.to_le_bytes());
+        data.extend(1i32.to_le_bytes());
+        data.extend(1i64.to_le_bytes());
+        for name in ["Payload", "Game", "Assembly-CSharp"] {
+            data.extend(string_data(name));
+        }
+        data.extend(0i32.to_le_bytes());
+        data.extend(material.to_le_bytes());
+        scene.replacements.insert(renderer, data);
+
+        scene
+    }
+
+    #[test]
+    fn managed_references_are_renumbered() {
+        let tpk = test_utils::tpk();
+        let scenes = [
+            managed_reference_scene(&tpk, "first", false),
+            managed_reference_scene(&tpk, "second", true),
+        ];
+        let (merged, remaps) = add_scenes_meta(&tpk, &scenes);
+
+        let ref_types = merged.m_RefTypes.as_deref().unwrap();
+        assert_eq!(
+            ref_types.len(),
+            1,
+            "the type is shared and the unused one left out"
+        );
+        assert_eq!(managed_type_key(&ref_types[0]).0, "Payload");
+        let dependencies = merged
+            .m_Types
+            .iter()
+            .filter_map(|ty| ty.m_TypeDependencies.clone())
+            .filter(|dependencies| !dependencies.is_empty())
+            .collect::<Vec<_>>();
+        assert_eq!(dependencies, [vec![0], vec![0]]);
+
+        let managed = ManagedTypes::of(&merged);
+        for (scene, remap) in scenes.into_iter().zip(remaps) {
+            let material = remap.path_id[&1];
+            let renderer = remap.path_id[&4];
+            let types = remap.types.clone();
+            let objects = remap_objects(
+                &scene.scene_name,
+                scene.original_name.clone(),
+                &scene.serialized,
+                scene.serialized_data.as_ref(),
+                &tpk,
+                scene.serialized.objects().cloned().collect(),
+                scene.replacements.clone(),
+                FxHashMap::default(),
+                remap,
+            )
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+            let (info, data) = objects
+                .iter()
+                .find(|(info, _)| info.m_PathID == renderer)
+                .unwrap();
+            assert!(types.values().any(|&ty| ty == info.m_TypeID));
+            let tt = merged.m_Types[info.m_TypeID as usize]
+                .m_Type
+                .as_ref()
+                .unwrap();
+            let references =
+                object_references(data, tt, merged.m_Header.m_Endianess, &managed).unwrap();
+            let target = references
+                .iter()
+                .find(|reference| reference.field.starts_with("references"))
+                .unwrap();
+            assert_eq!(target.field, "references.RefIds[0].target");
+            assert_eq!((target.file_id, target.path_id), (0, material));
+        }
+    }
 }
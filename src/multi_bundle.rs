@@ -0,0 +1,507 @@
+//! Splitting the repacked objects into several asset bundles, with the objects needed by more than
+//! one of them moved into a shared dependency bundle.
+use anyhow::{Context, Result, bail, ensure};
+use indexmap::{IndexMap, IndexSet};
+use rabex::UnityVersion;
+use rabex::files::SerializedFile;
+use rabex::files::bundlefile::CompressionType;
+use rabex::files::serializedfile;
+use rabex::files::serializedfile::builder::SerializedFileBuilder;
+use rabex::files::serializedfile::{FileIdentifier, ObjectInfo};
+use rabex::objects::ClassId;
+use rabex::objects::pptr::PathId;
+use rabex::tpk::TpkTypeTreeBlob;
+use rabex::typetree::{TypeTreeNode, TypeTreeProvider};
+use rabex_env::unity::types::{AssetBundle, AssetInfo};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::borrow::Cow;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use crate::checksum::{self, BundleChecksum};
+use crate::manifest::ManifestEntry;
+use crate::merge_serialized::MetaIndex;
+use crate::path_ids::{PathIdAllocator, PathIdStrategy};
+use crate::references::{ManagedTypes, local_references, rewrite_pptrs};
+use crate::{
+    ExtraObject, PackSettings, RepackScene, Stats, manifest, merge_serialized, objects,
+    scene_name_display,
+};
+
+/// How the requested objects are split into bundles
+#[derive(Debug, Clone)]
+pub enum BundleSplit {
+    /// One bundle per scene
+    PerScene,
+    /// One bundle per requested object
+    PerRoot,
+    /// Bundle names to the scenes or `scene/path` objects they contain.
+    /// Objects not in any group get a bundle per scene.
+    Groups(IndexMap<String, Vec<String>>),
+}
+
+impl BundleSplit {
+    fn group(&self, scene_name: &str, path: &str) -> String {
+        match self {
+            BundleSplit::PerScene => scene_name.to_owned(),
+            BundleSplit::PerRoot => format!("{scene_name}/{path}"),
+            BundleSplit::Groups(groups) => {
+                let asset_name = format!("{scene_name}/{path}");
+                groups
+                    .iter()
+                    .find(|(_, members)| {
+                        members
+                            .iter()
+                            .any(|member| *member == scene_name || *member == asset_name)
+                    })
+                    .map(|(name, _)| name.clone())
+                    .unwrap_or_else(|| scene_name.to_owned())
+            }
+        }
+    }
+}
+
+pub struct MultiBundle {
+    /// The shared bundle comes first, if there is one
+    pub bundles: Vec<PackedBundle>,
+    /// Bundle containing the `AssetBundleManifest` of all other bundles
+    pub manifest: PackedBundle,
+    pub stats: Stats,
+}
+
+pub struct PackedBundle {
+    pub name: String,
+    pub data: Vec<u8>,
+    /// Names of the bundles that have to be loaded before this one
+    pub dependencies: Vec<String>,
+    pub checksum: BundleChecksum,
+}
+
+impl PackedBundle {
+    pub fn manifest_entry(&self) -> ManifestEntry {
+        ManifestEntry {
+            name: self.name.clone(),
+            hash: self.checksum.hash,
+            dependencies: self.dependencies.clone(),
+        }
+    }
+}
+
+/// The shared bundle, and where the objects of each scene ended up in it
+struct SharedBundle {
+    name: String,
+    path_ids: Vec<FxHashMap<PathId, PathId>>,
+}
+
+/// Packs the scenes into one asset bundle per group of `split`, like [`pack_to_asset_bundle`](crate::pack_to_asset_bundle).
+///
+/// Objects reachable from the requested objects of more than one group are moved into a
+/// `{bundle_name}_shared` bundle, which the other bundles list in their `m_Dependencies`.
+/// Extra objects are added to the shared bundle as well.
+///
+/// The scenes have to be repacked with `prepare_scripts`, so the references of their MonoBehaviours
+/// can be renumbered.
+pub fn pack_to_multi_asset_bundle(
+    bundle_name: &str,
+    tpk_blob: &TpkTypeTreeBlob,
+    tpk: &impl TypeTreeProvider,
+    unity_version: &UnityVersion,
+    mut scenes: Vec<RepackScene>,
+    extra_objects: Vec<ExtraObject>,
+    split: &BundleSplit,
+    compression: CompressionType,
+    enable_typetree: bool,
+    settings: &PackSettings,
+) -> Result<MultiBundle> {
+    let common_offset_map = serializedfile::build_common_offset_map(tpk_blob, unity_version);
+    let mut stats = Stats::default();
+    stats.size_report = settings.check_size(&scenes, tpk)?;
+
+    // the group of every kept object, `None` for objects shared between groups
+    let mut groups = IndexSet::new();
+    let mut assignments = Vec::with_capacity(scenes.len());
+    for scene in &scenes {
+        stats.objects_before += scene.serialized.objects().len();
+        stats.size_before += scene.serialized_data.as_ref().len();
+
+        // the references of the other MonoBehaviours cannot be renumbered
+        let untyped = scene
+            .serialized
+            .objects()
+            .filter(|obj| {
+                scene.keep_objects.contains(&obj.m_PathID)
+                    && objects::class_id(&scene.serialized, obj) == ClassId::MonoBehaviour
+                    && !scene.monobehaviour_types.contains_key(&obj.m_PathID)
+            })
+            .count();
+        ensure!(
+            untyped == 0,
+            "{} contains {untyped} MonoBehaviours without typetree, repack with `prepare_scripts` to split it into several bundles",
+            scene_name_display(&scene.scene_name, &scene.original_name)
+        );
+
+        let references = local_references(scene, tpk)?;
+        let mut reached_by: FxHashMap<PathId, FxHashSet<usize>> = FxHashMap::default();
+        let mut grouped_roots = FxHashSet::default();
+        for (path, transform) in &scene.roots {
+            // objects kept with their ancestors can share a root, which goes with the first of them
+            if !grouped_roots.insert(transform.m_GameObject.m_PathID) {
+                continue;
+            }
+            let group = groups
+                .insert_full(bundle_file_name(
+                    bundle_name,
+                    &split.group(&scene.scene_name, path),
+                ))
+                .0;
+
+            let mut stack = vec![transform.m_GameObject.m_PathID];
+            while let Some(path_id) = stack.pop() {
+                if !references.contains_key(&path_id)
+                    || !reached_by.entry(path_id).or_default().insert(group)
+                {
+                    continue;
+                }
+                stack.extend(&references[&path_id]);
+            }
+        }
+
+        let assignment = references
+            .keys()
+            .map(|&path_id| {
+                let group = reached_by
+                    .get(&path_id)
+                    .filter(|groups| groups.len() == 1)
+                    .and_then(|groups| groups.iter().next().copied());
+                (path_id, group)
+            })
+            .collect::<FxHashMap<_, _>>();
+        assignments.push(assignment);
+    }
+
+    let shared_name = bundle_file_name(bundle_name, "shared");
+    if groups.contains(&shared_name) {
+        bail!("Bundle group 'shared' is reserved for the objects shared between groups");
+    }
+
+    let new_builder = || {
+        let mut builder =
+            SerializedFileBuilder::new(unity_version, tpk, &common_offset_map, enable_typetree);
+        builder.next_path_id = 2;
+        builder
+    };
+
+    let mut bundles = Vec::new();
+    let mut shared = None;
+
+    // every kept object goes into exactly one bundle, so the objects of each scene are split up once
+    let mut partitions = scenes
+        .iter_mut()
+        .zip(&assignments)
+        .map(|(scene, assignment)| {
+            let mut partition: FxHashMap<Option<usize>, Vec<ObjectInfo>> = FxHashMap::default();
+            for obj in scene.serialized.take_objects() {
+                if let Some(&group) = assignment.get(&obj.m_PathID) {
+                    partition.entry(group).or_default().push(obj);
+                }
+            }
+            partition
+        })
+        .collect::<Vec<_>>();
+    let mut take_partition = |group: Option<usize>| {
+        partitions
+            .iter_mut()
+            .map(|partition| partition.remove(&group).unwrap_or_default())
+            .collect::<Vec<_>>()
+    };
+
+    let shared_objects = take_partition(None);
+    if shared_objects.iter().any(|objects| !objects.is_empty()) || !extra_objects.is_empty() {
+        let mut builder = new_builder();
+        let mut asset_bundle = AssetBundle::asset_base(&shared_name);
+        crate::add_extra_objects(&mut builder, &mut asset_bundle, extra_objects)?;
+        let path_ids = add_objects(
+            &mut builder,
+            &mut asset_bundle,
+            &mut scenes,
+            shared_objects,
+            None,
+            tpk,
+            unity_version,
+            settings.path_ids,
+            &mut stats,
+        )?;
+        builder.add_object_at(1, &asset_bundle)?;
+
+        bundles.push(write_bundle(
+            builder,
+            &shared_name,
+            Vec::new(),
+            unity_version,
+            compression,
+            &mut stats,
+        )?);
+        shared = Some(SharedBundle {
+            name: shared_name,
+            path_ids,
+        });
+    }
+
+    for (group, name) in groups.iter().enumerate() {
+        let mut builder = new_builder();
+        let mut asset_bundle = AssetBundle::asset_base(name);
+        add_objects(
+            &mut builder,
+            &mut asset_bundle,
+            &mut scenes,
+            take_partition(Some(group)),
+            shared.as_ref(),
+            tpk,
+            unity_version,
+            settings.path_ids,
+            &mut stats,
+        )
+        .with_context(|| format!("Could not pack bundle '{name}'"))?;
+
+        let dependencies = shared
+            .as_ref()
+            .filter(|shared| {
+                let external_path = external_path(&shared.name);
+                builder
+                    .serialized
+                    .m_Externals
+                    .iter()
+                    .any(|external| external.pathName == external_path)
+            })
+            .map(|shared| vec![shared.name.clone()])
+            .unwrap_or_default();
+        asset_bundle.m_Dependencies.clone_from(&dependencies);
+        builder.add_object_at(1, &asset_bundle)?;
+
+        bundles.push(write_bundle(
+            builder,
+            name,
+            dependencies,
+            unity_version,
+            compression,
+            &mut stats,
+        )?);
+    }
+
+    let mut manifest_data = Cursor::new(Vec::new());
+    let manifest_checksum = manifest::pack_manifest_bundle(
+        &mut manifest_data,
+        bundle_name,
+        tpk_blob,
+        tpk,
+        unity_version,
+        &bundles
+            .iter()
+            .map(PackedBundle::manifest_entry)
+            .collect::<Vec<_>>(),
+        compression,
+    )?;
+
+    Ok(MultiBundle {
+        bundles,
+        manifest: PackedBundle {
+            name: bundle_name.to_owned(),
+            data: manifest_data.into_inner(),
+            dependencies: Vec::new(),
+            checksum: manifest_checksum,
+        },
+        stats,
+    })
+}
+
+/// Moves the `objects` of each of the `scenes` into `builder`, and adds the requested objects among
+/// them to the container.
+///
+/// References to objects in the `shared` bundle are pointed to it via a new external.
+/// Returns the new PathIds of the moved objects of each scene.
+fn add_objects(
+    builder: &mut SerializedFileBuilder<impl TypeTreeProvider>,
+    asset_bundle: &mut AssetBundle,
+    scenes: &mut [RepackScene],
+    objects: Vec<Vec<ObjectInfo>>,
+    shared: Option<&SharedBundle>,
+    tpk: &impl TypeTreeProvider,
+    unity_version: &UnityVersion,
+    path_id_strategy: PathIdStrategy,
+    stats: &mut Stats,
+) -> Result<Vec<FxHashMap<PathId, PathId>>> {
+    let mut path_ids = Vec::with_capacity(scenes.len());
+    let mut allocator = PathIdAllocator::new(path_id_strategy);
+    let mut meta = MetaIndex::default();
+
+    for (index, (scene, objects)) in scenes.iter_mut().zip(objects).enumerate() {
+        if objects.is_empty() {
+            path_ids.push(FxHashMap::default());
+            continue;
+        }
+        stats.objects_after += objects.len();
+
+        let file = &scene.serialized;
+        let data = scene.serialized_data.as_ref();
+
+        let mut replacements = FxHashMap::default();
+        let mut monobehaviour_types = FxHashMap::default();
+        for obj in &objects {
+            if let Some(replacement) = scene.replacements.remove(&obj.m_PathID) {
+                replacements.insert(obj.m_PathID, replacement);
+            }
+            if let Some(&ty) = scene.monobehaviour_types.get(&obj.m_PathID) {
+                monobehaviour_types.insert(obj.m_PathID, ty);
+            }
+        }
+
+        let mut remap = merge_serialized::add_scene_meta_to_builder(
+            builder,
+            &mut meta,
+            file,
+            objects.iter(),
+            &mut allocator,
+            &scene.scene_name,
+            &scene.original_name,
+        )?;
+        crate::add_missing_typetrees(builder, tpk, unity_version)?;
+
+        if let Some(shared) = shared {
+            let references_shared = point_to_shared(
+                file,
+                data,
+                &objects,
+                tpk,
+                &mut replacements,
+                &monobehaviour_types,
+                &shared.path_ids[index],
+            )
+            .with_context(|| format!("In {}", scene.scene_name))?;
+            if references_shared {
+                let shared_index = meta.add_external_index(
+                    builder,
+                    &FileIdentifier::try_from(PathBuf::from(external_path(&shared.name)))?,
+                );
+                remap.insert_external(file.m_Externals.len(), shared_index);
+            }
+        }
+
+        for (scene_path, transform) in &scene.roots {
+            let mut go = transform.m_GameObject;
+            let Some(&path_id) = remap.path_id.get(&go.m_PathID) else {
+                continue;
+            };
+            go.m_PathID = path_id;
+            asset_bundle.m_Container.insert(
+                crate::get_asset_bundle_object_asset_name(&scene.scene_name, scene_path),
+                AssetInfo::new(go.untyped()),
+            );
+        }
+
+        path_ids.push(remap.path_id.clone());
+
+        let objects = merge_serialized::remap_objects(
+            &scene.scene_name,
+            scene.original_name.clone(),
+            &builder.serialized,
+            data,
+            tpk,
+            objects,
+            replacements,
+            monobehaviour_types,
+            remap,
+        );
+        for obj in objects {
+            let (obj, data) = obj?;
+            builder.objects.insert(obj.m_PathID, (obj, data));
+        }
+    }
+
+    Ok(path_ids)
+}
+
+/// Points local references to objects that were moved into the shared bundle at the file id
+/// after the last external of `file`, which is then remapped to the shared bundle like the
+/// other externals.
+///
+/// Returns whether any object references the shared bundle.
+fn point_to_shared(
+    file: &SerializedFile,
+    data: &[u8],
+    objects: &[ObjectInfo],
+    tpk: &impl TypeTreeProvider,
+    replacements: &mut FxHashMap<PathId, Vec<u8>>,
+    monobehaviour_types: &FxHashMap<PathId, &TypeTreeNode>,
+    shared_path_ids: &FxHashMap<PathId, PathId>,
+) -> Result<bool> {
+    if shared_path_ids.is_empty() {
+        return Ok(false);
+    }
+
+    let shared_file_id = file.m_Externals.len() as i32 + 1;
+    let managed = ManagedTypes::of(file);
+    let mut references_shared = false;
+    for info in objects {
+        let tt = match monobehaviour_types.get(&info.m_PathID) {
+            Some(&tt) => Cow::Borrowed(tt),
+            None => file.get_typetree_for(info, tpk)?,
+        };
+        let mut object_data = objects::current_object_data(data, replacements, info).to_vec();
+        let changed = rewrite_pptrs(
+            &mut object_data,
+            &tt,
+            file.m_Header.m_Endianess,
+            &managed,
+            |file_id, path_id| {
+                let shared_path_id = shared_path_ids.get(&path_id).filter(|_| file_id == 0)?;
+                Some((shared_file_id, *shared_path_id))
+            },
+        )
+        .with_context(|| format!("Could not read object {}", info.m_PathID))?;
+        if changed {
+            references_shared = true;
+            replacements.insert(info.m_PathID, object_data);
+        }
+    }
+    Ok(references_shared)
+}
+
+fn write_bundle(
+    mut builder: SerializedFileBuilder<impl TypeTreeProvider>,
+    name: &str,
+    dependencies: Vec<String>,
+    unity_version: &UnityVersion,
+    compression: CompressionType,
+    stats: &mut Stats,
+) -> Result<PackedBundle> {
+    let mut out = Vec::new();
+    builder.write(&mut Cursor::new(&mut out))?;
+    stats.size_after += out.len();
+
+    let mut bundle = Cursor::new(Vec::new());
+    let checksum = checksum::write_unityfs(
+        &mut bundle,
+        unity_version,
+        vec![(format!("CAB-{name}"), out)],
+        compression,
+    )?;
+    Ok(PackedBundle {
+        name: name.to_owned(),
+        data: bundle.into_inner(),
+        dependencies,
+        checksum,
+    })
+}
+
+/// Unity lowercases bundle names, and they are used as file names
+fn bundle_file_name(bundle_name: &str, group: &str) -> String {
+    format!("{bundle_name}_{group}").to_lowercase().replace(
+        |c: char| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.')),
+        "_",
+    )
+}
+
+/// How files in other bundles are referenced
+fn external_path(bundle_name: &str) -> String {
+    format!("archive:/CAB-{bundle_name}/CAB-{bundle_name}")
+}
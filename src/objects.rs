@@ -0,0 +1,171 @@
+//! Helpers for reading and writing single objects of a [`SerializedFile`] through their typetree.
+use anyhow::{Context, Result};
+use rabex::files::SerializedFile;
+use rabex::files::serializedfile::ObjectInfo;
+use rabex::objects::ClassId;
+use rabex::objects::pptr::PathId;
+use rabex::serde_typetree;
+use rabex::typetree::{TypeTreeNode, TypeTreeProvider};
+use rabex_env::Environment;
+use rabex_env::handle::SerializedFileHandle;
+use rabex_env::unity::types::MonoBehaviour;
+use rustc_hash::FxHashMap;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use std::io::Cursor;
+
+pub fn class_id(file: &SerializedFile, info: &ObjectInfo) -> ClassId {
+    file.m_Types[info.m_TypeID as usize].m_ClassID
+}
+
+pub fn object_data<'a>(data: &'a [u8], info: &ObjectInfo) -> &'a [u8] {
+    let offset = info.m_Offset as usize;
+    let size = info.m_Size as usize;
+    &data[offset..offset + size]
+}
+
+/// Returns the replaced data of the object if there is one, or the original data from the file.
+pub fn current_object_data<'a>(
+    data: &'a [u8],
+    replacements: &'a FxHashMap<PathId, Vec<u8>>,
+    info: &ObjectInfo,
+) -> &'a [u8] {
+    match replacements.get(&info.m_PathID) {
+        Some(replacement) => replacement.as_slice(),
+        None => object_data(data, info),
+    }
+}
+
+pub fn object_infos(file: &SerializedFile) -> FxHashMap<PathId, &ObjectInfo> {
+    file.objects().map(|obj| (obj.m_PathID, obj)).collect()
+}
+
+pub fn read_object<T: DeserializeOwned>(
+    file: &SerializedFile,
+    info: &ObjectInfo,
+    object_data: &[u8],
+    tpk: &impl TypeTreeProvider,
+) -> Result<T> {
+    let tt = file.get_typetree_for(info, tpk)?;
+    read_object_with_typetree(file, info, object_data, &tt)
+}
+
+pub fn read_object_with_typetree<T: DeserializeOwned>(
+    file: &SerializedFile,
+    info: &ObjectInfo,
+    object_data: &[u8],
+    tt: &TypeTreeNode,
+) -> Result<T> {
+    serde_typetree::from_slice_endianed(object_data, tt, file.m_Header.m_Endianess)
+        .with_context(|| format!("Could not read object {}", info.m_PathID))
+}
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct NamedObject {
+    m_Name: String,
+}
+
+/// The `m_Name` of the object, or `None` if it has no name or it could not be read
+pub fn object_name(
+    file: &SerializedFile,
+    info: &ObjectInfo,
+    object_data: &[u8],
+    tt: &TypeTreeNode,
+) -> Option<String> {
+    read_object_with_typetree::<NamedObject>(file, info, object_data, tt)
+        .ok()
+        .map(|object| object.m_Name)
+        .filter(|name| !name.is_empty())
+}
+
+/// Serializes `value` using the typetree of the object, for use as a replacement.
+pub fn write_object<T: Serialize>(
+    file: &SerializedFile,
+    info: &ObjectInfo,
+    value: &T,
+    tpk: &impl TypeTreeProvider,
+) -> Result<Vec<u8>> {
+    let tt = file.get_typetree_for(info, tpk)?;
+    write_object_with_typetree(file, info, value, &tt)
+}
+
+pub fn write_object_with_typetree<T: Serialize>(
+    file: &SerializedFile,
+    info: &ObjectInfo,
+    value: &T,
+    tt: &TypeTreeNode,
+) -> Result<Vec<u8>> {
+    serde_typetree::to_vec_endianed(value, tt, file.m_Header.m_Endianess)
+        .with_context(|| format!("Could not write object {}", info.m_PathID))
+}
+
+/// Runs `f` on a view of the file where the objects in `patches` have the given data.
+///
+/// This is used to change objects before handing them to code that reads directly from the file,
+/// like the reachability analysis in `rabex_env::prune`.
+pub fn with_patched_objects<T>(
+    file: &mut SerializedFile,
+    data: &[u8],
+    patches: &FxHashMap<PathId, Vec<u8>>,
+    f: impl FnOnce(&SerializedFile, &mut Cursor<&[u8]>) -> T,
+) -> T {
+    if patches.is_empty() {
+        return f(file, &mut Cursor::new(data));
+    }
+
+    let mut patched_data = data.to_vec();
+    let mut original_locations = FxHashMap::default();
+    file.modify_objects(|objects| {
+        for obj in objects.iter_mut() {
+            let Some(patch) = patches.get(&obj.m_PathID) else {
+                continue;
+            };
+            original_locations.insert(obj.m_PathID, (obj.m_Offset, obj.m_Size));
+            obj.m_Offset = patched_data.len() as _;
+            obj.m_Size = patch.len() as _;
+            patched_data.extend_from_slice(patch);
+        }
+    });
+
+    let result = f(file, &mut Cursor::new(patched_data.as_slice()));
+
+    file.modify_objects(|objects| {
+        for obj in objects.iter_mut() {
+            if let Some(&(offset, size)) = original_locations.get(&obj.m_PathID) {
+                obj.m_Offset = offset;
+                obj.m_Size = size;
+            }
+        }
+    });
+
+    result
+}
+
+/// Names a component can be referred to by in the scene objects file: its class, and for
+/// `MonoBehaviour`s the script class name and full name.
+pub fn component_names(
+    env: &Environment,
+    file: &SerializedFile,
+    data: &[u8],
+) -> Result<FxHashMap<PathId, Vec<String>>> {
+    let mut names: FxHashMap<PathId, Vec<String>> = FxHashMap::default();
+    for info in file.objects() {
+        names
+            .entry(info.m_PathID)
+            .or_default()
+            .push(format!("{:?}", class_id(file, info)));
+    }
+
+    let handle = SerializedFileHandle::new(env, file, data);
+    for mb_obj in handle.objects_of::<MonoBehaviour>() {
+        if let Some(script) = mb_obj.mono_script()? {
+            let entry = names.entry(mb_obj.path_id()).or_default();
+            entry.push(script.full_name().into_owned());
+            entry.push(script.m_ClassName);
+        }
+    }
+
+    Ok(names)
+}
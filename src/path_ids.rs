@@ -0,0 +1,102 @@
+//! Assigning the PathIds of objects that are moved from their scene into a new file.
+use md4::{Digest, Md4};
+use rabex::files::serializedfile::builder::SerializedFileBuilder;
+use rabex::objects::pptr::PathId;
+use rabex::typetree::TypeTreeProvider;
+use rustc_hash::FxHashSet;
+use std::path::Path;
+
+/// How objects are numbered when several scenes are merged into one file,
+/// like in asset bundles or merged scenes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PathIdStrategy {
+    /// Number objects consecutively. Adding an object shifts the ids of all objects after it.
+    #[default]
+    Sequential,
+    /// Derive the id from the scene, original file and original PathId of the object,
+    /// so objects keep their id when the selection of other objects changes.
+    Stable,
+}
+
+/// Stable ids are kept above this, so they cannot clash with the sequential ids of objects
+/// created by the packer, like the `AssetBundle`
+const MIN_STABLE_PATH_ID: PathId = 1 << 32;
+
+pub(crate) struct PathIdAllocator {
+    strategy: PathIdStrategy,
+    used: FxHashSet<PathId>,
+}
+
+impl PathIdAllocator {
+    pub fn new(strategy: PathIdStrategy) -> Self {
+        PathIdAllocator {
+            strategy,
+            used: FxHashSet::default(),
+        }
+    }
+
+    pub fn next(
+        &mut self,
+        builder: &mut SerializedFileBuilder<impl TypeTreeProvider>,
+        scene_name: &str,
+        original_name: &Path,
+        path_id: PathId,
+    ) -> PathId {
+        match self.strategy {
+            PathIdStrategy::Sequential => builder.get_next_path_id(),
+            PathIdStrategy::Stable => {
+                // on collision, rehash with a counter. This only moves the object that came later.
+                let mut attempt = 0u32;
+                loop {
+                    let candidate = stable_path_id(scene_name, original_name, path_id, attempt);
+                    if candidate >= MIN_STABLE_PATH_ID && self.used.insert(candidate) {
+                        return candidate;
+                    }
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+fn stable_path_id(scene_name: &str, original_name: &Path, path_id: PathId, attempt: u32) -> PathId {
+    let digest = Md4::new()
+        .chain_update(scene_name.as_bytes())
+        .chain_update([0])
+        .chain_update(original_name.as_os_str().as_encoded_bytes())
+        .chain_update([0])
+        .chain_update(path_id.to_le_bytes())
+        .chain_update(attempt.to_le_bytes())
+        .finalize();
+    let bytes: [u8; 8] = digest[..8].try_into().unwrap();
+    i64::from_le_bytes(bytes) & i64::MAX
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+    use rabex::files::serializedfile;
+
+    #[test]
+    fn collisions_fall_back_to_the_next_attempt() {
+        let tpk = test_utils::tpk();
+        let unity_version = test_utils::unity_version();
+        let common_offset_map = serializedfile::build_common_offset_map(&tpk.inner, &unity_version);
+        let mut builder =
+            SerializedFileBuilder::new(&unity_version, &tpk, &common_offset_map, false);
+        let mut path_ids = PathIdAllocator::new(PathIdStrategy::Stable);
+        let original_name = Path::new("level1");
+
+        // the same object twice, like the same scene being packed again
+        let first = path_ids.next(&mut builder, "scene", original_name, 5);
+        let second = path_ids.next(&mut builder, "scene", original_name, 5);
+        assert_eq!(first, stable_path_id("scene", original_name, 5, 0));
+        assert_eq!(second, stable_path_id("scene", original_name, 5, 1));
+        assert_ne!(first, second);
+        assert!(first >= MIN_STABLE_PATH_ID && second >= MIN_STABLE_PATH_ID);
+
+        let other = path_ids.next(&mut builder, "scene", original_name, 6);
+        assert_eq!(other, stable_path_id("scene", original_name, 6, 0));
+    }
+}
@@ -0,0 +1,589 @@
+//! Finding `PPtr`s in objects, either typetree-decoded or directly in their serialized data.
+use anyhow::{Context, Result, bail, ensure};
+use rabex::files::SerializedFile;
+use rabex::files::serializedfile::{Endianness, SerializedType};
+use rabex::objects::pptr::PathId;
+use rabex::typetree::{TypeTreeNode, TypeTreeProvider};
+use rustc_hash::FxHashMap;
+use serde_json::Value;
+use std::borrow::Cow;
+
+use crate::{RepackScene, objects};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    /// Field path inside the object, e.g. `m_Component[0].component`
+    pub field: String,
+    pub file_id: i32,
+    pub path_id: PathId,
+}
+
+/// Collects every non-null `PPtr` in the object
+pub fn collect_references(value: &Value) -> Vec<Reference> {
+    fn walk(value: &Value, field: &mut String, out: &mut Vec<Reference>) {
+        match value {
+            Value::Object(map) => {
+                if let (Some(file_id), Some(path_id)) = (
+                    map.get("m_FileID").and_then(Value::as_i64),
+                    map.get("m_PathID").and_then(Value::as_i64),
+                ) && map.len() == 2
+                {
+                    if path_id != 0 {
+                        out.push(Reference {
+                            field: field.clone(),
+                            file_id: file_id as i32,
+                            path_id,
+                        });
+                    }
+                    return;
+                }
+
+                for (key, value) in map {
+                    let len = field.len();
+                    if !field.is_empty() {
+                        field.push('.');
+                    }
+                    field.push_str(key);
+                    walk(value, field, out);
+                    field.truncate(len);
+                }
+            }
+            Value::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    let len = field.len();
+                    field.push_str(&format!("[{i}]"));
+                    walk(item, field, out);
+                    field.truncate(len);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut out = Vec::new();
+    walk(value, &mut String::new(), &mut out);
+    out
+}
+
+/// The typetrees of the `[SerializeReference]` types of a file (`m_RefTypes`), which describe the
+/// data of the managed references stored in its objects
+#[derive(Default)]
+pub struct ManagedTypes<'a> {
+    types: FxHashMap<(&'a str, &'a str, &'a str), &'a TypeTreeNode>,
+}
+
+impl<'a> ManagedTypes<'a> {
+    pub fn of(file: &'a SerializedFile) -> Self {
+        let types = file
+            .m_RefTypes
+            .iter()
+            .flatten()
+            .filter_map(|ty| Some((managed_type_key(ty), ty.m_Type.as_ref()?)))
+            .collect();
+        ManagedTypes { types }
+    }
+}
+
+/// Class, namespace and assembly of a type in `m_RefTypes`, which is how managed references name
+/// their type
+pub(crate) fn managed_type_key(ty: &SerializedType) -> (&str, &str, &str) {
+    (
+        ty.m_KlassName.as_deref().unwrap_or_default(),
+        ty.m_NameSpace.as_deref().unwrap_or_default(),
+        ty.m_AsmName.as_deref().unwrap_or_default(),
+    )
+}
+
+/// Collects every non-null `PPtr` in the serialized data of an object, without decoding the rest
+/// of it. Large arrays of plain data like vertices or pixels are skipped in one step.
+///
+/// `PPtr`s in managed references are found using the typetrees in `managed`.
+pub fn object_references(
+    data: &[u8],
+    tt: &TypeTreeNode,
+    endianness: Endianness,
+    managed: &ManagedTypes,
+) -> Result<Vec<Reference>> {
+    Ok(find_pptrs(data, tt, endianness, managed)?
+        .into_iter()
+        .map(|location| location.reference)
+        .collect())
+}
+
+/// Replaces every non-null `PPtr` in the serialized data for which `f` returns a new
+/// `(file_id, path_id)`.
+///
+/// Use [`replace_pptrs_inplace_endianed`](rabex_env::trace_pptr::replace_pptrs_inplace_endianed)
+/// when the new pointer only depends on the old path id or file id, this is for the cases where it
+/// depends on both, or for objects with managed references. Returns whether anything was replaced.
+pub fn rewrite_pptrs(
+    data: &mut [u8],
+    tt: &TypeTreeNode,
+    endianness: Endianness,
+    managed: &ManagedTypes,
+    mut f: impl FnMut(i32, PathId) -> Option<(i32, PathId)>,
+) -> Result<bool> {
+    let big_endian = endianness == Endianness::Big;
+    let mut changed = false;
+    for location in find_pptrs(data, tt, endianness, managed)? {
+        let reference = location.reference;
+        let Some((file_id, path_id)) = f(reference.file_id, reference.path_id) else {
+            continue;
+        };
+
+        let offset = location.offset;
+        let file_id = match big_endian {
+            true => file_id.to_be_bytes(),
+            false => file_id.to_le_bytes(),
+        };
+        data[offset..offset + 4].copy_from_slice(&file_id);
+        if location.wide_path_id {
+            let path_id = match big_endian {
+                true => path_id.to_be_bytes(),
+                false => path_id.to_le_bytes(),
+            };
+            data[offset + 4..offset + 12].copy_from_slice(&path_id);
+        } else {
+            let path_id = i32::try_from(path_id).with_context(|| {
+                format!("PathId {path_id} does not fit into '{}'", reference.field)
+            })?;
+            let path_id = match big_endian {
+                true => path_id.to_be_bytes(),
+                false => path_id.to_le_bytes(),
+            };
+            data[offset + 4..offset + 8].copy_from_slice(&path_id);
+        }
+        changed = true;
+    }
+    Ok(changed)
+}
+
+/// The objects each kept object of the scene references in the same file
+pub(crate) fn local_references(
+    scene: &RepackScene,
+    tpk: &impl TypeTreeProvider,
+) -> Result<FxHashMap<PathId, Vec<PathId>>> {
+    let file = &scene.serialized;
+    let data = scene.serialized_data.as_ref();
+
+    let managed = ManagedTypes::of(file);
+    let mut references = FxHashMap::default();
+    for info in file.objects() {
+        if !scene.keep_objects.contains(&info.m_PathID) {
+            continue;
+        }
+
+        let object_data = objects::current_object_data(data, &scene.replacements, info);
+        let tt = match scene.monobehaviour_types.get(&info.m_PathID) {
+            Some(&tt) => Cow::Borrowed(tt),
+            None => file.get_typetree_for(info, tpk)?,
+        };
+        let object_references =
+            object_references(object_data, &tt, file.m_Header.m_Endianess, &managed)
+                .with_context(|| format!("Could not read object {}", info.m_PathID))
+                .with_context(|| format!("In {}", scene.scene_name))?;
+
+        references.insert(
+            info.m_PathID,
+            object_references
+                .into_iter()
+                .filter(|reference| reference.file_id == 0)
+                .map(|reference| reference.path_id)
+                .collect(),
+        );
+    }
+    Ok(references)
+}
+
+/// Set on fields which are followed by padding to a multiple of four bytes
+const ALIGN_BYTES: i32 = 0x4000;
+
+struct PPtrLocation {
+    reference: Reference,
+    /// Offset of `m_FileID`, directly followed by `m_PathID`
+    offset: usize,
+    /// Whether `m_PathID` is 8 bytes, which is the case from format version 14 onwards
+    wide_path_id: bool,
+}
+
+fn find_pptrs(
+    data: &[u8],
+    tt: &TypeTreeNode,
+    endianness: Endianness,
+    managed: &ManagedTypes,
+) -> Result<Vec<PPtrLocation>> {
+    let mut walker = Walker {
+        data,
+        position: 0,
+        big_endian: endianness == Endianness::Big,
+        field: String::new(),
+        out: Vec::new(),
+        managed,
+        managed_type: None,
+    };
+    for child in &tt.children {
+        walker.walk(child)?;
+    }
+    Ok(walker.out)
+}
+
+struct Walker<'a> {
+    data: &'a [u8],
+    position: usize,
+    big_endian: bool,
+    field: String,
+    out: Vec<PPtrLocation>,
+    managed: &'a ManagedTypes<'a>,
+    /// Class, namespace and assembly of the managed reference whose data comes next
+    managed_type: Option<[String; 3]>,
+}
+
+impl Walker<'_> {
+    fn walk(&mut self, node: &TypeTreeNode) -> Result<()> {
+        let len = self.field.len();
+        if node.m_Name != "Array" && node.m_Name != "data" {
+            if !self.field.is_empty() {
+                self.field.push('.');
+            }
+            self.field.push_str(&node.m_Name);
+        }
+
+        if node.m_Type.starts_with("PPtr<") {
+            let offset = self.position;
+            let wide_path_id = node
+                .children
+                .get(1)
+                .is_some_and(|path_id| primitive_size(&path_id.m_Type) == Some(8));
+            let file_id = self.read_i32()?;
+            let path_id = match wide_path_id {
+                true => self.read_i64()?,
+                false => self.read_i32()? as i64,
+            };
+            if path_id != 0 {
+                self.out.push(PPtrLocation {
+                    reference: Reference {
+                        field: self.field.clone(),
+                        file_id,
+                        path_id,
+                    },
+                    offset,
+                    wide_path_id,
+                });
+            }
+        } else if node.m_Type == "ManagedReferencesRegistry" {
+            let version = self.peek_i32()?;
+            ensure!(
+                version >= 2,
+                "Managed references version {version} in '{}' is not supported",
+                self.field
+            );
+            for child in &node.children {
+                self.walk(child)?;
+            }
+        } else if node.m_Type == "ReferencedManagedType" {
+            let mut names = <[String; 3]>::default();
+            for (name, child) in names.iter_mut().zip(&node.children) {
+                *name = self.read_string(child)?;
+            }
+            self.managed_type = Some(names);
+        } else if node.m_Type == "ReferencedObjectData" {
+            let [class, namespace, assembly] = self
+                .managed_type
+                .take()
+                .with_context(|| format!("Managed reference without a type in '{}'", self.field))?;
+            // null references have no type and no data
+            if !class.is_empty() {
+                let key = (class.as_str(), namespace.as_str(), assembly.as_str());
+                let tt = self.managed.types.get(&key).with_context(|| {
+                    format!(
+                        "Missing typetree of managed reference type {namespace}.{class} ({assembly}) in '{}'",
+                        self.field
+                    )
+                })?;
+                for child in &tt.children {
+                    self.walk(child)?;
+                }
+            }
+        } else if is_array(node) {
+            let count = self.read_i32()?;
+            let count = usize::try_from(count)
+                .with_context(|| format!("Invalid array length {count} in '{}'", self.field))?;
+            let element = &node.children[1];
+            match fixed_size(element) {
+                Some(size) => self.skip(count * size)?,
+                None => {
+                    for i in 0..count {
+                        let len = self.field.len();
+                        self.field.push_str(&format!("[{i}]"));
+                        self.walk(element)?;
+                        self.field.truncate(len);
+                    }
+                }
+            }
+        } else if let Some(size) = primitive_size(&node.m_Type) {
+            self.skip(size)?;
+        } else if !node.children.is_empty() {
+            for child in &node.children {
+                self.walk(child)?;
+            }
+        } else {
+            bail!(
+                "Cannot read field '{}' of unknown type '{}'",
+                self.field,
+                node.m_Type
+            );
+        }
+
+        if is_aligned(node) {
+            self.position = self.position.next_multiple_of(4);
+        }
+        self.field.truncate(len);
+        Ok(())
+    }
+
+    fn skip(&mut self, size: usize) -> Result<()> {
+        let end = self.position + size;
+        if end > self.data.len() {
+            bail!(
+                "Object data ends in field '{}' ({end} > {})",
+                self.field,
+                self.data.len()
+            );
+        }
+        self.position = end;
+        Ok(())
+    }
+
+    fn read<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let start = self.position;
+        self.skip(N)?;
+        Ok(self.data[start..start + N].try_into().unwrap())
+    }
+
+    fn read_i32(&mut self) -> Result<i32> {
+        let bytes = self.read()?;
+        Ok(match self.big_endian {
+            true => i32::from_be_bytes(bytes),
+            false => i32::from_le_bytes(bytes),
+        })
+    }
+
+    fn peek_i32(&mut self) -> Result<i32> {
+        let start = self.position;
+        let value = self.read_i32();
+        self.position = start;
+        value
+    }
+
+    /// Reads the `string` field `node`
+    fn read_string(&mut self, node: &TypeTreeNode) -> Result<String> {
+        let start = self.position;
+        let len = self.peek_i32()?;
+        self.walk(node)?;
+        let len = usize::try_from(len)
+            .with_context(|| format!("Invalid string length {len} in '{}'", self.field))?;
+        let bytes = &self.data[start + 4..start + 4 + len];
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    fn read_i64(&mut self) -> Result<i64> {
+        let bytes = self.read()?;
+        Ok(match self.big_endian {
+            true => i64::from_be_bytes(bytes),
+            false => i64::from_le_bytes(bytes),
+        })
+    }
+}
+
+fn is_array(node: &TypeTreeNode) -> bool {
+    (node.m_Type == "Array" || node.m_Type == "TypelessData") && node.children.len() == 2
+}
+
+fn is_aligned(node: &TypeTreeNode) -> bool {
+    node.m_MetaFlag
+        .is_some_and(|flags| flags & ALIGN_BYTES != 0)
+}
+
+fn primitive_size(ty: &str) -> Option<usize> {
+    Some(match ty {
+        "bool" | "char" | "SInt8" | "UInt8" => 1,
+        "SInt16" | "UInt16" | "short" | "unsigned short" => 2,
+        "SInt32" | "UInt32" | "int" | "unsigned int" | "float" | "Type*" => 4,
+        "SInt64" | "UInt64" | "long long" | "unsigned long long" | "double" | "FileSize" => 8,
+        _ => return None,
+    })
+}
+
+/// The size of a value of this type if it never varies and contains no `PPtr`s
+fn fixed_size(node: &TypeTreeNode) -> Option<usize> {
+    if is_aligned(node) || node.m_Type.starts_with("PPtr<") || is_array(node) {
+        return None;
+    }
+    if let Some(size) = primitive_size(&node.m_Type) {
+        return Some(size);
+    }
+    if node.children.is_empty() {
+        return None;
+    }
+    node.children.iter().map(fixed_size).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{self, Tpk, field};
+    use rabex::objects::ClassId;
+
+    /// An object consisting of `fields`
+    fn object(tpk: &Tpk, fields: Vec<TypeTreeNode>) -> TypeTreeNode {
+        let mut tt = tpk
+            .get_typetree_node(ClassId::GameObject, &test_utils::unity_version())
+            .unwrap()
+            .into_owned();
+        tt.children = fields;
+        tt
+    }
+
+    fn pptr_data(file_id: i32, path_id: i64) -> Vec<u8> {
+        [&file_id.to_le_bytes()[..], &path_id.to_le_bytes()].concat()
+    }
+
+    fn references(
+        data: &[u8],
+        tt: &TypeTreeNode,
+        endianness: Endianness,
+    ) -> Vec<(String, i32, PathId)> {
+        object_references(data, tt, endianness, &ManagedTypes::default())
+            .unwrap()
+            .into_iter()
+            .map(|reference| (reference.field, reference.file_id, reference.path_id))
+            .collect()
+    }
+
+    #[test]
+    fn aligned_bool_before_pptr() {
+        let tpk = test_utils::tpk();
+        let mut active = field(&tpk, ClassId::GameObject, "m_IsActive");
+        active.m_MetaFlag = Some(ALIGN_BYTES);
+        let tt = object(
+            &tpk,
+            vec![active, field(&tpk, ClassId::MeshRenderer, "m_GameObject")],
+        );
+
+        let data = [&[1, 0, 0, 0][..], &pptr_data(0, 7)].concat();
+        assert_eq!(
+            references(&data, &tt, Endianness::Little),
+            [("m_GameObject".to_owned(), 0, 7)]
+        );
+    }
+
+    #[test]
+    fn array_of_structs_with_pptrs() {
+        let tpk = test_utils::tpk();
+        let tt = object(&tpk, vec![field(&tpk, ClassId::GameObject, "m_Component")]);
+
+        let data = [&2i32.to_le_bytes()[..], &pptr_data(0, 2), &pptr_data(1, 3)].concat();
+        assert_eq!(
+            references(&data, &tt, Endianness::Little),
+            [
+                ("m_Component[0].component".to_owned(), 0, 2),
+                ("m_Component[1].component".to_owned(), 1, 3),
+            ]
+        );
+
+        let mut data = data;
+        let changed = rewrite_pptrs(
+            &mut data,
+            &tt,
+            Endianness::Little,
+            &ManagedTypes::default(),
+            |file_id, path_id| (file_id == 1).then_some((2, path_id + 10)),
+        )
+        .unwrap();
+        assert!(changed);
+        assert_eq!(
+            data,
+            [&2i32.to_le_bytes()[..], &pptr_data(0, 2), &pptr_data(2, 13)].concat()
+        );
+    }
+
+    #[test]
+    fn narrow_path_ids() {
+        let tpk = test_utils::tpk();
+        let mut pptr = field(&tpk, ClassId::MeshRenderer, "m_GameObject");
+        pptr.children[1].m_Type = "SInt32".to_owned();
+        let tt = object(&tpk, vec![pptr.clone(), pptr]);
+
+        let data = [1i32, 9, 0, 4]
+            .into_iter()
+            .flat_map(i32::to_le_bytes)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            references(&data, &tt, Endianness::Little),
+            [
+                ("m_GameObject".to_owned(), 1, 9),
+                ("m_GameObject".to_owned(), 0, 4)
+            ]
+        );
+
+        let mut rewritten = data.clone();
+        rewrite_pptrs(
+            &mut rewritten,
+            &tt,
+            Endianness::Little,
+            &ManagedTypes::default(),
+            |_, path_id| Some((0, path_id + 1)),
+        )
+        .unwrap();
+        let expected = [0i32, 10, 0, 5]
+            .into_iter()
+            .flat_map(i32::to_le_bytes)
+            .collect::<Vec<_>>();
+        assert_eq!(rewritten, expected);
+
+        let mut too_wide = data;
+        let error = rewrite_pptrs(
+            &mut too_wide,
+            &tt,
+            Endianness::Little,
+            &ManagedTypes::default(),
+            |file_id, _| Some((file_id, 1 << 40)),
+        )
+        .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("does not fit into 'm_GameObject'"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn big_endian() {
+        let tpk = test_utils::tpk();
+        let tt = object(
+            &tpk,
+            vec![field(&tpk, ClassId::MeshRenderer, "m_GameObject")],
+        );
+
+        let data = [&1i32.to_be_bytes()[..], &0x0102_0304_0506i64.to_be_bytes()].concat();
+        assert_eq!(
+            references(&data, &tt, Endianness::Big),
+            [("m_GameObject".to_owned(), 1, 0x0102_0304_0506)]
+        );
+
+        let mut data = data;
+        rewrite_pptrs(
+            &mut data,
+            &tt,
+            Endianness::Big,
+            &ManagedTypes::default(),
+            |_, _| Some((2, 0x0708)),
+        )
+        .unwrap();
+        assert_eq!(
+            data,
+            [&2i32.to_be_bytes()[..], &0x0708i64.to_be_bytes()].concat()
+        );
+    }
+}
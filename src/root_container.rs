@@ -0,0 +1,193 @@
+//! Synthesizing a GameObject that all roots of a repacked scene are moved under.
+use anyhow::Result;
+use log::warn;
+use rabex::objects::ClassId;
+use rabex::typetree::TypeTreeProvider;
+
+use crate::unity_types::{
+    ComponentPair, GameObject, Quaternionf, RectTransform, Transform, Vector2f, Vector3f,
+};
+use crate::{RepackScene, objects};
+
+/// Name of the GameObject created by [`PackSettings::root_container`](crate::PackSettings::root_container)
+#[derive(Debug, Clone)]
+pub enum RootContainer {
+    /// Named after the scene the objects are from
+    SceneName,
+    Named(String),
+}
+
+impl RootContainer {
+    fn name<'a>(&'a self, scene_name: &'a str) -> &'a str {
+        match self {
+            RootContainer::SceneName => scene_name,
+            RootContainer::Named(name) => name,
+        }
+    }
+}
+
+/// Adds a new root GameObject with a Transform to the scene, and reparents all kept roots under it
+pub(crate) fn add_root_container(
+    scene: &mut RepackScene,
+    container: &RootContainer,
+    tpk: &impl TypeTreeProvider,
+) -> Result<()> {
+    let file = &scene.serialized;
+    let data = scene.serialized_data.as_ref();
+
+    let mut roots = Vec::new();
+    let mut go_info = None;
+    let mut transform_info = None;
+    let mut rect_transform_info = None;
+    for info in file.objects() {
+        if !scene.keep_objects.contains(&info.m_PathID) {
+            continue;
+        }
+        match objects::class_id(file, info) {
+            ClassId::GameObject => go_info = go_info.or(Some(info)),
+            ClassId::Transform | ClassId::RectTransform => {
+                let transform: Transform = objects::read_object(
+                    file,
+                    info,
+                    objects::current_object_data(data, &scene.replacements, info),
+                    tpk,
+                )?;
+                match objects::class_id(file, info) {
+                    ClassId::Transform => transform_info = transform_info.or(Some(info)),
+                    _ => rect_transform_info = rect_transform_info.or(Some(info)),
+                }
+                if transform.m_Father.is_null() {
+                    roots.push((info, transform));
+                }
+            }
+            _ => {}
+        }
+    }
+    let Some((_, first_root)) = roots.first() else {
+        return Ok(());
+    };
+    // scenes with only UI have no plain Transform, the container then gets a RectTransform
+    let Some(transform_info) = transform_info.or(rect_transform_info) else {
+        warn!(
+            "No Transform in {} to base the root container on, leaving its roots unwrapped",
+            scene.scene_name
+        );
+        return Ok(());
+    };
+    let Some(go_info) = go_info else {
+        warn!(
+            "No GameObject in {} to base the root container on, leaving its roots unwrapped",
+            scene.scene_name
+        );
+        return Ok(());
+    };
+
+    // the new objects reuse the type of existing ones
+    let max_path_id = file.objects().map(|info| info.m_PathID).max().unwrap_or(0);
+    let mut new_go_info = go_info.clone();
+    new_go_info.m_PathID = max_path_id + 1;
+    let mut new_transform_info = transform_info.clone();
+    new_transform_info.m_PathID = max_path_id + 2;
+
+    let mut go_pptr = first_root.m_GameObject;
+    go_pptr.m_PathID = new_go_info.m_PathID;
+    let mut transform_pptr = first_root.m_GameObject;
+    transform_pptr.m_PathID = new_transform_info.m_PathID;
+
+    let go = GameObject {
+        m_Component: vec![ComponentPair {
+            component: transform_pptr,
+        }],
+        m_Layer: 0,
+        m_Name: container.name(&scene.scene_name).to_owned(),
+        m_Tag: 0,
+        // with disabled roots, the container is disabled instead, so the whole batch can be
+        // enabled at once
+        m_IsActive: !scene.roots_disabled,
+    };
+    let mut transform = Transform {
+        m_GameObject: go_pptr,
+        m_LocalRotation: Quaternionf::IDENTITY,
+        m_LocalPosition: Vector3f::default(),
+        m_LocalScale: Vector3f::ONE,
+        m_Children: Vec::new(),
+        m_Father: first_root.m_Father,
+    };
+
+    let mut replacements = Vec::new();
+    if scene.roots_disabled {
+        let infos = objects::object_infos(file);
+        for (_, root) in &roots {
+            let go_id = root.m_GameObject.m_PathID;
+            let Some(&go_info) = infos.get(&go_id) else {
+                continue;
+            };
+            let original: GameObject =
+                objects::read_object(file, go_info, objects::object_data(data, go_info), tpk)?;
+            let mut go: GameObject = objects::read_object(
+                file,
+                go_info,
+                objects::current_object_data(data, &scene.replacements, go_info),
+                tpk,
+            )?;
+            go.m_IsActive = original.m_IsActive;
+            replacements.push((go_id, objects::write_object(file, go_info, &go, tpk)?));
+        }
+    }
+
+    for (info, mut root) in roots {
+        let mut child_pptr = transform_pptr;
+        child_pptr.m_PathID = info.m_PathID;
+        transform.m_Children.push(child_pptr);
+
+        let object_data = objects::current_object_data(data, &scene.replacements, info);
+        let replacement = match objects::class_id(file, info) {
+            ClassId::RectTransform => {
+                let mut rect: RectTransform = objects::read_object(file, info, object_data, tpk)?;
+                rect.m_Father = transform_pptr;
+                objects::write_object(file, info, &rect, tpk)?
+            }
+            _ => {
+                root.m_Father = transform_pptr;
+                objects::write_object(file, info, &root, tpk)?
+            }
+        };
+        replacements.push((info.m_PathID, replacement));
+    }
+
+    replacements.push((
+        new_go_info.m_PathID,
+        objects::write_object(file, &new_go_info, &go, tpk)?,
+    ));
+    let transform_data = match objects::class_id(file, transform_info) {
+        ClassId::RectTransform => {
+            let center = Vector2f { x: 0.5, y: 0.5 };
+            let rect = RectTransform {
+                m_GameObject: transform.m_GameObject,
+                m_LocalRotation: transform.m_LocalRotation,
+                m_LocalPosition: transform.m_LocalPosition,
+                m_LocalScale: transform.m_LocalScale,
+                m_Children: transform.m_Children,
+                m_Father: transform.m_Father,
+                m_AnchorMin: center,
+                m_AnchorMax: center,
+                m_AnchoredPosition: Vector2f::default(),
+                m_SizeDelta: Vector2f::default(),
+                m_Pivot: center,
+            };
+            objects::write_object(file, &new_transform_info, &rect, tpk)?
+        }
+        _ => objects::write_object(file, &new_transform_info, &transform, tpk)?,
+    };
+    replacements.push((new_transform_info.m_PathID, transform_data));
+
+    scene.replacements.extend(replacements);
+    scene.keep_objects.insert(new_go_info.m_PathID);
+    scene.keep_objects.insert(new_transform_info.m_PathID);
+    scene.serialized.modify_objects(|objects| {
+        objects.push(new_go_info);
+        objects.push(new_transform_info);
+    });
+
+    Ok(())
+}
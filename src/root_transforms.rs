@@ -0,0 +1,257 @@
+//! Rewriting the `Transform` of objects that were promoted to scene roots.
+use anyhow::{Context, Result};
+use log::warn;
+use rabex::files::SerializedFile;
+use rabex::files::serializedfile::ObjectInfo;
+use rabex::objects::ClassId;
+use rabex::objects::pptr::PathId;
+use rabex::typetree::TypeTreeProvider;
+use rabex_env::unity::types::Transform as PrunedRoot;
+use rustc_hash::FxHashMap;
+
+use crate::objects;
+use crate::scene_tree::SceneTree;
+use crate::unity_types::{Quaternionf, RectTransform, Transform, Vector2f, Vector3f};
+
+/// What to do with the local transform of an object that becomes a root when repacking
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RootTransforms {
+    /// Keep the local transform, which was relative to the now missing parents
+    #[default]
+    Local,
+    /// Bake the transforms of all former parents into the root, so it keeps its world position
+    World,
+    /// Reset position and rotation to zero and scale to one
+    Identity,
+}
+
+#[derive(Clone, Copy)]
+struct Trs {
+    position: Vector3f,
+    rotation: Quaternionf,
+    scale: Vector3f,
+}
+
+impl Trs {
+    const IDENTITY: Trs = Trs {
+        position: Vector3f {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        },
+        rotation: Quaternionf::IDENTITY,
+        scale: Vector3f::ONE,
+    };
+
+    fn of(transform: &Transform) -> Trs {
+        Trs {
+            position: transform.m_LocalPosition,
+            rotation: transform.m_LocalRotation,
+            scale: transform.m_LocalScale,
+        }
+    }
+
+    /// Applies `local` relative to `self`.
+    /// Like unity's `lossyScale`, skew from non-uniform scale on rotated parents is dropped.
+    fn then(self, local: Trs) -> Trs {
+        Trs {
+            position: self
+                .position
+                .add(self.rotation.rotate(self.scale.scale(local.position))),
+            rotation: self.rotation.mul(local.rotation),
+            scale: self.scale.scale(local.scale),
+        }
+    }
+}
+
+/// Rewrites the transforms of the `roots` returned from pruning, storing them in `replacements`.
+pub(crate) fn rewrite_root_transforms(
+    file: &SerializedFile,
+    data: &[u8],
+    tpk: &impl TypeTreeProvider,
+    roots: &[(String, PrunedRoot)],
+    mode: RootTransforms,
+    replacements: &mut FxHashMap<PathId, Vec<u8>>,
+) -> Result<()> {
+    if mode == RootTransforms::Local || roots.is_empty() {
+        return Ok(());
+    }
+
+    let tree = SceneTree::build(file, data, tpk)?;
+    let infos = objects::object_infos(file);
+
+    // roots are matched by GameObject, as paths are ambiguous for roots with the same name
+    let transforms = tree
+        .nodes
+        .values()
+        .map(|node| (node.game_object, node.transform))
+        .collect::<FxHashMap<_, _>>();
+
+    for (path, root) in roots {
+        let Some(&transform_id) = transforms.get(&root.m_GameObject.m_PathID) else {
+            warn!("Could not find transform of '{path}', keeping its local transform");
+            continue;
+        };
+        let info = infos[&transform_id];
+
+        let parent = match mode {
+            RootTransforms::World => world_transform(&tree, file, &infos, data, tpk, transform_id)?,
+            _ => Trs::IDENTITY,
+        };
+
+        let object_data = objects::current_object_data(data, replacements, info);
+        let replacement = match objects::class_id(file, info) {
+            ClassId::RectTransform => {
+                let mut rect: RectTransform = objects::read_object(file, info, object_data, tpk)?;
+                let new = match mode {
+                    RootTransforms::World => parent.then(Trs {
+                        position: rect.m_LocalPosition,
+                        rotation: rect.m_LocalRotation,
+                        scale: rect.m_LocalScale,
+                    }),
+                    _ => Trs::IDENTITY,
+                };
+                rect.m_LocalPosition = new.position;
+                rect.m_LocalRotation = new.rotation;
+                rect.m_LocalScale = new.scale;
+
+                // without a parent rect the anchors have no meaning, so anchor to the center of
+                // whatever the object gets parented to, keeping the baked position
+                if rect.m_AnchorMin != rect.m_AnchorMax {
+                    let action = match mode {
+                        RootTransforms::World => "baking",
+                        _ => "resetting",
+                    };
+                    warn!(
+                        "'{path}' has stretched anchors, its size will not be preserved when {action} its transform"
+                    );
+                }
+                let center = Vector2f { x: 0.5, y: 0.5 };
+                rect.m_AnchorMin = center;
+                rect.m_AnchorMax = center;
+                rect.m_AnchoredPosition = Vector2f {
+                    x: new.position.x,
+                    y: new.position.y,
+                };
+                objects::write_object(file, info, &rect, tpk)?
+            }
+            _ => {
+                let mut transform: Transform = objects::read_object(file, info, object_data, tpk)?;
+                let new = match mode {
+                    RootTransforms::World => parent.then(Trs::of(&transform)),
+                    _ => Trs::IDENTITY,
+                };
+                transform.m_LocalPosition = new.position;
+                transform.m_LocalRotation = new.rotation;
+                transform.m_LocalScale = new.scale;
+                objects::write_object(file, info, &transform, tpk)?
+            }
+        };
+        replacements.insert(transform_id, replacement);
+    }
+
+    Ok(())
+}
+
+/// World transform of the original parent of `transform_id`
+fn world_transform(
+    tree: &SceneTree,
+    file: &SerializedFile,
+    infos: &FxHashMap<PathId, &ObjectInfo>,
+    data: &[u8],
+    tpk: &impl TypeTreeProvider,
+    transform_id: PathId,
+) -> Result<Trs> {
+    let mut ancestors = Vec::new();
+    let mut current = tree.nodes[&transform_id].father;
+    while let Some(ancestor) = current {
+        ancestors.push(ancestor);
+        current = tree.nodes.get(&ancestor).and_then(|node| node.father);
+    }
+
+    let mut world = Trs::IDENTITY;
+    for &ancestor in ancestors.iter().rev() {
+        let info = infos
+            .get(&ancestor)
+            .with_context(|| format!("Missing parent transform {ancestor}"))?;
+        // RectTransforms start with the same fields, and keep their local position up to date
+        let transform: Transform =
+            objects::read_object(file, info, objects::object_data(data, info), tpk)?;
+        world = world.then(Trs::of(&transform));
+    }
+    Ok(world)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HALF_SQRT_2: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+    fn vec3(x: f32, y: f32, z: f32) -> Vector3f {
+        Vector3f { x, y, z }
+    }
+
+    fn quat(x: f32, y: f32, z: f32, w: f32) -> Quaternionf {
+        Quaternionf { x, y, z, w }
+    }
+
+    #[track_caller]
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        let close = actual
+            .iter()
+            .zip(expected)
+            .all(|(a, b)| (a - b).abs() < 1e-5);
+        assert!(close, "{actual:?} != {expected:?}");
+    }
+
+    #[test]
+    fn rotations_compose_like_unity() {
+        let x90 = quat(HALF_SQRT_2, 0.0, 0.0, HALF_SQRT_2);
+        let y90 = quat(0.0, HALF_SQRT_2, 0.0, HALF_SQRT_2);
+
+        // Quaternion.Euler(90, 90, 0), which applies the rotation around x first
+        let q = y90.mul(x90);
+        assert_close(&[q.x, q.y, q.z, q.w], &[0.5, 0.5, -0.5, 0.5]);
+        let forward = q.rotate(vec3(0.0, 0.0, 1.0));
+        assert_close(&[forward.x, forward.y, forward.z], &[0.0, -1.0, 0.0]);
+
+        let right = y90.rotate(vec3(1.0, 0.0, 0.0));
+        assert_close(&[right.x, right.y, right.z], &[0.0, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn nested_transforms_are_baked() {
+        let y90 = quat(0.0, HALF_SQRT_2, 0.0, HALF_SQRT_2);
+        let grandparent = Trs {
+            position: vec3(0.0, 10.0, 0.0),
+            rotation: Quaternionf::IDENTITY,
+            scale: vec3(1.0, 1.0, 1.0),
+        };
+        let parent = Trs {
+            position: vec3(1.0, 2.0, 3.0),
+            rotation: y90,
+            scale: vec3(2.0, 2.0, 2.0),
+        };
+        let child = Trs {
+            position: vec3(1.0, 0.0, 0.0),
+            rotation: y90,
+            scale: vec3(1.0, 3.0, 1.0),
+        };
+
+        let world = Trs::IDENTITY.then(grandparent).then(parent).then(child);
+
+        // the child is 2 units along the parent's x axis, which points to -z after the rotation
+        let Trs {
+            position,
+            rotation,
+            scale,
+        } = world;
+        assert_close(&[position.x, position.y, position.z], &[1.0, 12.0, 1.0]);
+        assert_close(
+            &[rotation.x, rotation.y, rotation.z, rotation.w],
+            &[0.0, 1.0, 0.0, 0.0],
+        );
+        assert_close(&[scale.x, scale.y, scale.z], &[2.0, 6.0, 2.0]);
+    }
+}
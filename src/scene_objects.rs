@@ -0,0 +1,126 @@
+//! Entries of the scene objects file.
+use serde_derive::{Deserialize, Serialize};
+
+/// A requested object, either just its path or its path with additional options.
+///
+/// - Paths starting with `!` exclude that subtree from the other requested objects.
+/// - Entries with `exclude_components` remove those components from the object instead of
+///   requesting it.
+/// - `*` keeps every root of the scene, to be combined with exclusions.
+///
+/// ```json5
+/// {
+///   "Fungus1_12": [
+///     "simple_grass",
+///     { "path": "_UI/Canvas/Button", "keep_ancestors": ["Canvas", "CanvasScaler"] },
+///     "Boss Arena",
+///     "!Boss Arena/Music Region",
+///     { "path": "Boss Arena/Boss", "exclude_components": ["HealthManager"] },
+///   ],
+///   "Town": ["*", "!_Enemies"]
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SceneObject {
+    Path(String),
+    Options(SceneObjectOptions),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SceneObjectOptions {
+    pub path: String,
+    /// Keep the parents of the object as placeholders, instead of making it a root
+    #[serde(default, skip_serializing_if = "KeepAncestors::is_disabled")]
+    pub keep_ancestors: KeepAncestors,
+    /// Class or script names of components to remove from the object
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_components: Vec<String>,
+}
+
+/// `true` keeps only the `Transform` of every ancestor, a list of class or script names
+/// additionally keeps those components.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum KeepAncestors {
+    Enabled(bool),
+    WithComponents(Vec<String>),
+}
+
+impl Default for KeepAncestors {
+    fn default() -> Self {
+        KeepAncestors::Enabled(false)
+    }
+}
+
+impl KeepAncestors {
+    pub fn is_disabled(&self) -> bool {
+        matches!(self, KeepAncestors::Enabled(false))
+    }
+
+    /// Components to keep besides the `Transform`, or `None` if ancestors are not kept
+    pub fn components(&self) -> Option<&[String]> {
+        match self {
+            KeepAncestors::Enabled(false) => None,
+            KeepAncestors::Enabled(true) => Some(&[]),
+            KeepAncestors::WithComponents(components) => Some(components),
+        }
+    }
+}
+
+impl SceneObject {
+    /// The path of the object, without the `!` of exclusions
+    pub fn path(&self) -> &str {
+        match self {
+            SceneObject::Path(path) => path.strip_prefix('!').unwrap_or(path),
+            SceneObject::Options(options) => &options.path,
+        }
+    }
+
+    /// The entry that keeps all roots of the scene
+    pub const WHOLE_SCENE: &str = "*";
+
+    pub fn is_whole_scene(&self) -> bool {
+        matches!(self, SceneObject::Path(path) if path == SceneObject::WHOLE_SCENE)
+    }
+
+    /// Whether the entry removes something instead of requesting an object
+    pub fn is_exclusion(&self) -> bool {
+        match self {
+            SceneObject::Path(path) => path.starts_with('!'),
+            SceneObject::Options(options) => !options.exclude_components.is_empty(),
+        }
+    }
+
+    pub fn excluded_components(&self) -> &[String] {
+        match self {
+            SceneObject::Path(_) => &[],
+            SceneObject::Options(options) => &options.exclude_components,
+        }
+    }
+
+    pub fn keep_ancestors(&self) -> Option<&[String]> {
+        match self {
+            SceneObject::Path(_) => None,
+            SceneObject::Options(options) => options.keep_ancestors.components(),
+        }
+    }
+
+    /// The same entry with a different path, keeping all options
+    pub fn with_path(&self, path: String) -> SceneObject {
+        match self {
+            SceneObject::Path(_) if self.is_exclusion() => SceneObject::Path(format!("!{path}")),
+            SceneObject::Path(_) => SceneObject::Path(path),
+            SceneObject::Options(options) => SceneObject::Options(SceneObjectOptions {
+                path,
+                ..options.clone()
+            }),
+        }
+    }
+}
+
+impl From<String> for SceneObject {
+    fn from(path: String) -> Self {
+        SceneObject::Path(path)
+    }
+}
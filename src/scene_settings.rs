@@ -0,0 +1,136 @@
+//! Keeping the scene-global settings objects, which are not part of the transform hierarchy.
+use anyhow::Result;
+use log::warn;
+use rabex::files::SerializedFile;
+use rabex::objects::ClassId;
+use rabex::objects::pptr::PathId;
+use rabex::typetree::TypeTreeProvider;
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+use crate::references::{collect_references, local_references};
+use crate::{RepackScene, objects};
+
+const SCENE_SETTINGS: [ClassId; 4] = [
+    ClassId::RenderSettings,
+    ClassId::LightmapSettings,
+    ClassId::OcclusionCullingSettings,
+    ClassId::NavMeshSettings,
+];
+
+/// Adds the render, lightmap, occlusion and navmesh settings of the scene to `keep_objects`,
+/// together with the assets they reference, like the skybox material or lightmap textures.
+///
+/// References into the GameObject hierarchy (like `RenderSettings.m_Sun`) are not followed,
+/// those objects are only kept if they were requested.
+pub(crate) fn keep_scene_settings(
+    file: &SerializedFile,
+    data: &[u8],
+    tpk: &impl TypeTreeProvider,
+    replacements: &FxHashMap<PathId, Vec<u8>>,
+    keep_objects: &mut BTreeSet<PathId>,
+) -> Result<()> {
+    let infos = objects::object_infos(file);
+
+    let mut stack = file
+        .objects()
+        .filter(|info| SCENE_SETTINGS.contains(&objects::class_id(file, info)))
+        .map(|info| (info.m_PathID, None))
+        .collect::<Vec<_>>();
+
+    while let Some((path_id, field)) = stack.pop() {
+        let Some(info) = infos.get(&path_id) else {
+            continue;
+        };
+        if field.is_some() && keep_objects.contains(&path_id) {
+            continue;
+        }
+
+        let class_id = objects::class_id(file, info);
+        let is_hierarchy = matches!(
+            class_id,
+            ClassId::GameObject
+                | ClassId::Transform
+                | ClassId::RectTransform
+                | ClassId::MonoBehaviour
+        );
+        let value: Option<Value> = match is_hierarchy {
+            true => None,
+            false => Some(objects::read_object(
+                file,
+                info,
+                objects::current_object_data(data, replacements, info),
+                tpk,
+            )?),
+        };
+        // components have a `m_GameObject`, and are only kept with their GameObject
+        let Some(value) = value.filter(|value| value.get("m_GameObject").is_none()) else {
+            warn!(
+                "Scene settings reference {class_id:?} {path_id} in '{}', which is not kept because it was not requested",
+                field.unwrap_or_default()
+            );
+            continue;
+        };
+
+        keep_objects.insert(path_id);
+        stack.extend(
+            collect_references(&value)
+                .into_iter()
+                .filter(|reference| reference.file_id == 0)
+                .map(|reference| (reference.path_id, Some(reference.field))),
+        );
+    }
+
+    Ok(())
+}
+
+/// Removes the scene settings from the kept objects of the scene, together with the assets only
+/// they reference.
+///
+/// A scene can only have one of each setting, so this is done for all but the first scene when
+/// merging scenes.
+pub(crate) fn drop_scene_settings(
+    scene: &mut RepackScene,
+    tpk: &impl TypeTreeProvider,
+) -> Result<()> {
+    let file = &scene.serialized;
+    let settings = file
+        .objects()
+        .filter(|info| {
+            scene.keep_objects.contains(&info.m_PathID)
+                && SCENE_SETTINGS.contains(&objects::class_id(file, info))
+        })
+        .map(|info| info.m_PathID)
+        .collect::<Vec<_>>();
+    if settings.is_empty() {
+        return Ok(());
+    }
+
+    let references = local_references(scene, tpk)?;
+    let reachable = |start: &mut dyn Iterator<Item = PathId>| {
+        let mut reachable = FxHashSet::default();
+        let mut stack = start.collect::<Vec<_>>();
+        while let Some(path_id) = stack.pop() {
+            if let Some(targets) = references.get(&path_id)
+                && reachable.insert(path_id)
+            {
+                stack.extend(targets);
+            }
+        }
+        reachable
+    };
+
+    let from_settings = reachable(&mut settings.iter().copied());
+    let used_elsewhere = reachable(
+        &mut references
+            .keys()
+            .copied()
+            .filter(|path_id| !from_settings.contains(path_id)),
+    );
+    scene
+        .keep_objects
+        .retain(|path_id| !from_settings.contains(path_id) || used_elsewhere.contains(path_id));
+
+    Ok(())
+}
@@ -0,0 +1,129 @@
+//! In-memory index of the transform hierarchy of a scene file.
+use anyhow::Result;
+use rabex::files::SerializedFile;
+use rabex::files::serializedfile::ObjectInfo;
+use rabex::objects::ClassId;
+use rabex::objects::pptr::PathId;
+use rabex::typetree::TypeTreeProvider;
+use rustc_hash::FxHashMap;
+
+use crate::objects;
+use crate::unity_types::{GameObject, RectTransform, Transform};
+
+pub struct SceneNode {
+    pub transform: PathId,
+    pub game_object: PathId,
+    pub name: String,
+    pub father: Option<PathId>,
+    pub children: Vec<PathId>,
+}
+
+pub struct SceneTree {
+    /// Nodes keyed by the PathId of their `Transform`
+    pub nodes: FxHashMap<PathId, SceneNode>,
+    pub roots: Vec<PathId>,
+}
+
+impl SceneTree {
+    pub fn build(
+        file: &SerializedFile,
+        data: &[u8],
+        tpk: &impl TypeTreeProvider,
+    ) -> Result<SceneTree> {
+        let infos = objects::object_infos(file);
+
+        let mut nodes = FxHashMap::default();
+        let mut roots = Vec::new();
+
+        for info in file.objects() {
+            let class_id = objects::class_id(file, info);
+            if class_id != ClassId::Transform && class_id != ClassId::RectTransform {
+                continue;
+            }
+
+            let transform: Transform =
+                objects::read_object(file, info, objects::object_data(data, info), tpk)?;
+            let Some(go_info) = infos.get(&transform.m_GameObject.m_PathID) else {
+                continue;
+            };
+            let go: GameObject =
+                objects::read_object(file, go_info, objects::object_data(data, go_info), tpk)?;
+
+            let father = (!transform.m_Father.is_null()).then_some(transform.m_Father.m_PathID);
+            if father.is_none() {
+                roots.push(info.m_PathID);
+            }
+
+            nodes.insert(
+                info.m_PathID,
+                SceneNode {
+                    transform: info.m_PathID,
+                    game_object: go_info.m_PathID,
+                    name: go.m_Name,
+                    father,
+                    children: transform
+                        .m_Children
+                        .iter()
+                        .map(|child| child.m_PathID)
+                        .collect(),
+                },
+            );
+        }
+
+        Ok(SceneTree { nodes, roots })
+    }
+
+    /// The `/`-separated path of the transform, as used in the scene objects file
+    pub fn path(&self, transform: PathId) -> String {
+        let mut segments = Vec::new();
+        let mut current = Some(transform);
+        while let Some(node) = current.and_then(|id| self.nodes.get(&id)) {
+            segments.push(node.name.as_str());
+            current = node.father;
+        }
+        segments.reverse();
+        segments.join("/")
+    }
+
+    pub fn lookup_path(&self, path: &str) -> Option<PathId> {
+        let mut segments = path.split('/');
+        let first = segments.next()?;
+        let mut current = *self
+            .roots
+            .iter()
+            .find(|&root| self.nodes[root].name == first)?;
+        for segment in segments {
+            current = *self.nodes[&current]
+                .children
+                .iter()
+                .find(|child| self.nodes.get(child).is_some_and(|c| c.name == segment))?;
+        }
+        Some(current)
+    }
+
+    pub fn find_by_name<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a SceneNode> + 'a {
+        self.nodes.values().filter(move |node| node.name == name)
+    }
+}
+
+/// Serializes the `Transform` or `RectTransform` with only the children matching `keep`
+pub fn retain_children(
+    file: &SerializedFile,
+    info: &ObjectInfo,
+    object_data: &[u8],
+    tpk: &impl TypeTreeProvider,
+    mut keep: impl FnMut(PathId) -> bool,
+) -> Result<Vec<u8>> {
+    match objects::class_id(file, info) {
+        ClassId::RectTransform => {
+            let mut rect: RectTransform = objects::read_object(file, info, object_data, tpk)?;
+            rect.m_Children.retain(|child| keep(child.m_PathID));
+            objects::write_object(file, info, &rect, tpk)
+        }
+        _ => {
+            let mut transform: Transform = objects::read_object(file, info, object_data, tpk)?;
+            transform.m_Children.retain(|child| keep(child.m_PathID));
+            objects::write_object(file, info, &transform, tpk)
+        }
+    }
+}
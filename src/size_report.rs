@@ -0,0 +1,248 @@
+//! Breakdown of the repacked output size by requested root, class and scene.
+use anyhow::Result;
+use log::warn;
+use rabex::objects::ClassId;
+use rabex::objects::pptr::PathId;
+use rabex::typetree::TypeTreeProvider;
+use rabex_env::unity::types::MonoBehaviour;
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde_derive::Serialize;
+
+use crate::references::local_references;
+use crate::{RepackScene, objects};
+
+/// Name of the entry in [`SizeReport::by_root`] for objects not reachable from any root
+pub const UNATTRIBUTED: &str = "<unattributed>";
+
+#[derive(Debug, Default, Serialize)]
+pub struct SizeReport {
+    /// Objects shared between multiple roots are split evenly between them
+    pub by_root: Vec<SizeEntry>,
+    pub by_class: Vec<SizeEntry>,
+    pub by_scene: Vec<SizeEntry>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SizeEntry {
+    pub name: String,
+    pub objects: usize,
+    pub bytes: usize,
+}
+
+#[derive(Default)]
+struct Accumulator(FxHashMap<String, (usize, f64)>);
+
+impl Accumulator {
+    fn add(&mut self, name: &str, objects: usize, bytes: f64) {
+        let entry = self.0.entry(name.to_owned()).or_default();
+        entry.0 += objects;
+        entry.1 += bytes;
+    }
+
+    fn into_sorted(self) -> Vec<SizeEntry> {
+        let mut entries = self
+            .0
+            .into_iter()
+            .map(|(name, (objects, bytes))| SizeEntry {
+                name,
+                objects,
+                bytes: bytes.round() as usize,
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.name.cmp(&b.name)));
+        entries
+    }
+}
+
+/// Computes the size of every kept object, and attributes it to the roots it is reachable from.
+///
+/// References in the fields of MonoBehaviours are only followed if the scenes were repacked with
+/// `prepare_scripts`, otherwise the objects only they use end up in [`UNATTRIBUTED`].
+pub fn size_report(scenes: &[RepackScene], tpk: &impl TypeTreeProvider) -> Result<SizeReport> {
+    let mut by_root = Accumulator::default();
+    let mut by_class = Accumulator::default();
+    let mut by_scene = Accumulator::default();
+
+    let mut untyped_scripts = 0;
+    for scene in scenes {
+        let file = &scene.serialized;
+        let data = scene.serialized_data.as_ref();
+        untyped_scripts += count_untyped_scripts(scene, tpk)?;
+
+        let references = local_references(scene, tpk)?;
+        let mut sizes = FxHashMap::default();
+        for info in file.objects() {
+            if !scene.keep_objects.contains(&info.m_PathID) {
+                continue;
+            }
+
+            let object_data = objects::current_object_data(data, &scene.replacements, info);
+            sizes.insert(info.m_PathID, object_data.len());
+
+            let class = format!("{:?}", objects::class_id(file, info));
+            by_class.add(&class, 1, object_data.len() as f64);
+            by_scene.add(&scene.scene_name, 1, object_data.len() as f64);
+        }
+
+        let mut reached_by: FxHashMap<PathId, Vec<usize>> = FxHashMap::default();
+        for (root_index, (_, transform)) in scene.roots.iter().enumerate() {
+            let mut visited = FxHashSet::default();
+            let mut stack = vec![transform.m_GameObject.m_PathID];
+            while let Some(path_id) = stack.pop() {
+                if !sizes.contains_key(&path_id) || !visited.insert(path_id) {
+                    continue;
+                }
+                reached_by.entry(path_id).or_default().push(root_index);
+                stack.extend(references.get(&path_id).into_iter().flatten());
+            }
+        }
+
+        let root_names = scene
+            .roots
+            .iter()
+            .map(|(path, _)| format!("{}/{path}", scene.scene_name))
+            .collect::<Vec<_>>();
+        for (path_id, size) in sizes {
+            match reached_by.get(&path_id) {
+                Some(roots) => {
+                    let share = size as f64 / roots.len() as f64;
+                    for &root in roots {
+                        by_root.add(&root_names[root], 1, share);
+                    }
+                }
+                None => by_root.add(UNATTRIBUTED, 1, size as f64),
+            }
+        }
+    }
+
+    if untyped_scripts > 0 {
+        warn!(
+            "{untyped_scripts} MonoBehaviours have no typetree for their script, objects only they reference are counted as {UNATTRIBUTED}"
+        );
+    }
+
+    Ok(SizeReport {
+        by_root: by_root.into_sorted(),
+        by_class: by_class.into_sorted(),
+        by_scene: by_scene.into_sorted(),
+    })
+}
+
+/// Kept MonoBehaviours with a script whose fields cannot be read
+fn count_untyped_scripts(scene: &RepackScene, tpk: &impl TypeTreeProvider) -> Result<usize> {
+    let file = &scene.serialized;
+    let data = scene.serialized_data.as_ref();
+
+    let mut count = 0;
+    for info in file.objects() {
+        if !scene.keep_objects.contains(&info.m_PathID)
+            || scene.monobehaviour_types.contains_key(&info.m_PathID)
+            || objects::class_id(file, info) != ClassId::MonoBehaviour
+        {
+            continue;
+        }
+        let object_data = objects::current_object_data(data, &scene.replacements, info);
+        let mb: MonoBehaviour = objects::read_object(file, info, object_data, tpk)?;
+        count += usize::from(!mb.m_Script.is_null());
+    }
+    Ok(count)
+}
+
+/// Limits on the pruned objects, checked before the bundle is written.
+///
+/// Sizes are measured in uncompressed object bytes, like in [`SizeReport`].
+#[derive(Debug, Default, Clone)]
+pub struct Budget {
+    /// Sum of all objects, not the size of the written bundle
+    pub max_object_bytes: Option<usize>,
+    /// Does not apply to [`UNATTRIBUTED`], see `max_unattributed_bytes`
+    pub max_root_bytes: Option<usize>,
+    pub max_class_bytes: Option<usize>,
+    pub max_objects: Option<usize>,
+    /// Objects not reachable from any root, e.g. kept scene settings or objects whose
+    /// references could not be traced
+    pub max_unattributed_bytes: Option<usize>,
+}
+
+impl Budget {
+    pub fn is_empty(&self) -> bool {
+        self.max_object_bytes.is_none()
+            && self.max_root_bytes.is_none()
+            && self.max_class_bytes.is_none()
+            && self.max_objects.is_none()
+            && self.max_unattributed_bytes.is_none()
+    }
+
+    /// Fails with a list of the offenders if any limit is exceeded
+    pub fn check(&self, report: &SizeReport) -> Result<()> {
+        const MAX_OFFENDERS: usize = 10;
+
+        let mut violations = Vec::new();
+
+        let object_bytes: usize = report.by_scene.iter().map(|entry| entry.bytes).sum();
+        let total_objects: usize = report.by_scene.iter().map(|entry| entry.objects).sum();
+        if let Some(max) = self.max_object_bytes.filter(|&max| object_bytes > max) {
+            violations.push(format!(
+                "objects total {object_bytes} bytes, exceeding {max}"
+            ));
+        }
+        if let Some(max) = self.max_objects.filter(|&max| total_objects > max) {
+            violations.push(format!("{total_objects} objects exceed {max}"));
+        }
+
+        let unattributed = report
+            .by_root
+            .iter()
+            .find(|entry| entry.name == UNATTRIBUTED);
+        let unattributed_bytes = unattributed.map_or(0, |entry| entry.bytes);
+        match self.max_unattributed_bytes {
+            Some(max) if unattributed_bytes > max => violations.push(format!(
+                "{unattributed_bytes} bytes are not attributed to any root, exceeding {max}"
+            )),
+            None if unattributed_bytes > 0 && self.max_root_bytes.is_some() => warn!(
+                "{unattributed_bytes} bytes are not attributed to any root and not covered by the root size limit"
+            ),
+            _ => {}
+        }
+
+        for (kind, max, entries) in [
+            ("root", self.max_root_bytes, &report.by_root),
+            ("class", self.max_class_bytes, &report.by_class),
+        ] {
+            let Some(max) = max else { continue };
+            // entries are sorted by size, so the offenders come first
+            let offenders = entries
+                .iter()
+                .take_while(|entry| entry.bytes > max)
+                .filter(|entry| kind != "root" || entry.name != UNATTRIBUTED)
+                .collect::<Vec<_>>();
+            for entry in offenders.iter().take(MAX_OFFENDERS) {
+                violations.push(format!(
+                    "{kind} '{}' is {} bytes ({} objects), exceeding {max}",
+                    entry.name, entry.bytes, entry.objects
+                ));
+            }
+            if offenders.len() > MAX_OFFENDERS {
+                violations.push(format!(
+                    "... and {} more {kind}s over budget",
+                    offenders.len() - MAX_OFFENDERS
+                ));
+            }
+        }
+
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        let mut message = String::from("Size budget exceeded:");
+        for violation in violations {
+            message.push_str("\n  - ");
+            message.push_str(&violation);
+        }
+        message.push_str("\nLargest roots:");
+        for entry in report.by_root.iter().take(MAX_OFFENDERS) {
+            message.push_str(&format!("\n  {}: {} bytes", entry.name, entry.bytes));
+        }
+        anyhow::bail!(message)
+    }
+}
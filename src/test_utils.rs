@@ -0,0 +1,334 @@
+//! Small scenes built in memory, so the packing can be tested without game files.
+use rabex::UnityVersion;
+use rabex::files::SerializedFile;
+use rabex::files::serializedfile;
+use rabex::files::serializedfile::FileIdentifier;
+use rabex::files::serializedfile::builder::SerializedFileBuilder;
+use rabex::objects::pptr::PathId;
+use rabex::objects::{ClassId, ClassIdType};
+use rabex::tpk::TpkTypeTreeBlob;
+use rabex::typetree::typetree_cache::sync::TypeTreeCache;
+use rabex::typetree::{TypeTreeNode, TypeTreeProvider};
+use rabex_env::Environment;
+use rabex_env::env::Data;
+use rabex_env::game_files::GameFiles;
+use serde::{Serialize, Serializer};
+use serde_json::{Value, json};
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use crate::{RepackScene, SceneTarget, objects};
+
+pub(crate) type Tpk = TypeTreeCache<TpkTypeTreeBlob>;
+
+pub(crate) fn tpk() -> Tpk {
+    TypeTreeCache::new(TpkTypeTreeBlob::embedded())
+}
+
+pub(crate) fn unity_version() -> UnityVersion {
+    "2020.2.2f1".parse().unwrap()
+}
+
+/// A value for every field of `node`, with zeroes, empty strings and empty arrays
+pub(crate) fn default_value(node: &TypeTreeNode) -> Value {
+    match node.m_Type.as_str() {
+        "bool" => false.into(),
+        "float" | "double" => 0.0.into(),
+        "string" => "".into(),
+        "TypelessData" => Value::Array(Vec::new()),
+        ty if ty.starts_with("PPtr<") => pptr(0),
+        _ if node.children.is_empty() => 0.into(),
+        _ if node.children[0].m_Type == "Array" => Value::Array(Vec::new()),
+        _ => Value::Object(
+            node.children
+                .iter()
+                .map(|child| (child.m_Name.clone(), default_value(child)))
+                .collect(),
+        ),
+    }
+}
+
+/// A copy of the field `name` of the typetree of `class_id`, for building other typetrees
+pub(crate) fn field(tpk: &Tpk, class_id: ClassId, name: &str) -> TypeTreeNode {
+    let tt = tpk
+        .get_typetree_node(class_id, &unity_version())
+        .unwrap_or_else(|| panic!("no typetree for {class_id:?}"));
+    tt.children
+        .iter()
+        .find(|child| child.m_Name == name)
+        .unwrap_or_else(|| panic!("no field {name} in {class_id:?}"))
+        .clone()
+}
+
+pub(crate) fn pptr(path_id: PathId) -> Value {
+    json!({ "m_FileID": 0, "m_PathID": path_id })
+}
+
+/// Objects written through their typetree, as `add_object_at` needs a type per class
+macro_rules! value_objects {
+    ($($class:ident),*) => {$(
+        struct $class(Value);
+
+        impl ClassIdType for $class {
+            const CLASS_ID: ClassId = ClassId::$class;
+        }
+
+        impl Serialize for $class {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                self.0.serialize(serializer)
+            }
+        }
+    )*};
+}
+value_objects!(
+    GameObject,
+    Transform,
+    MeshRenderer,
+    Material,
+    MonoBehaviour,
+    MonoScript,
+    BuildSettings
+);
+
+/// A scene of roots with a `MeshRenderer` each, and the materials they use
+pub(crate) struct TestScene {
+    name: String,
+    objects: Vec<(PathId, ClassId, Value)>,
+    /// Name and `Transform` of each root
+    roots: Vec<(String, PathId)>,
+    externals: Vec<String>,
+}
+
+impl TestScene {
+    pub fn new(name: &str) -> Self {
+        TestScene {
+            name: name.to_owned(),
+            objects: Vec::new(),
+            roots: Vec::new(),
+            externals: Vec::new(),
+        }
+    }
+
+    pub fn add_external(&mut self, path: &str) {
+        self.externals.push(path.to_owned());
+    }
+
+    fn add(&mut self, tpk: &Tpk, class_id: ClassId, fields: Value) -> PathId {
+        let path_id = self.objects.len() as PathId + 1;
+        let tt = tpk
+            .get_typetree_node(class_id, &unity_version())
+            .unwrap_or_else(|| panic!("no typetree for {class_id:?}"));
+        let mut value = default_value(&tt);
+        for (field, field_value) in fields.as_object().unwrap() {
+            value[field] = field_value.clone();
+        }
+        self.objects.push((path_id, class_id, value));
+        path_id
+    }
+
+    pub fn add_material(&mut self, tpk: &Tpk, name: &str) -> PathId {
+        self.add(tpk, ClassId::Material, json!({ "m_Name": name }))
+    }
+
+    /// Adds a root GameObject rendering `material`, returns the PathId of its `MeshRenderer`
+    pub fn add_renderer(&mut self, tpk: &Tpk, name: &str, material: PathId) -> PathId {
+        self.add_renderer_with(tpk, name, pptr(material))
+    }
+
+    /// Like [`TestScene::add_renderer`], with a material in the external file `file_id`
+    pub fn add_external_renderer(
+        &mut self,
+        tpk: &Tpk,
+        name: &str,
+        file_id: i32,
+        material: PathId,
+    ) -> PathId {
+        let material = json!({ "m_FileID": file_id, "m_PathID": material });
+        self.add_renderer_with(tpk, name, material)
+    }
+
+    fn add_renderer_with(&mut self, tpk: &Tpk, name: &str, material: Value) -> PathId {
+        // the objects are numbered in the order they are added
+        let go = self.objects.len() as PathId + 1;
+        let (transform, renderer) = (go + 1, go + 2);
+
+        self.add(
+            tpk,
+            ClassId::GameObject,
+            json!({
+                "m_Component": [{ "component": pptr(transform) }, { "component": pptr(renderer) }],
+                "m_Name": name,
+                "m_IsActive": true,
+            }),
+        );
+        self.add(
+            tpk,
+            ClassId::Transform,
+            json!({
+                "m_GameObject": pptr(go),
+                "m_LocalRotation": { "x": 0.0, "y": 0.0, "z": 0.0, "w": 1.0 },
+                "m_LocalScale": { "x": 1.0, "y": 1.0, "z": 1.0 },
+            }),
+        );
+        self.add(
+            tpk,
+            ClassId::MeshRenderer,
+            json!({
+                "m_GameObject": pptr(go),
+                "m_Materials": [material],
+            }),
+        );
+        self.roots.push((name.to_owned(), transform));
+        renderer
+    }
+
+    /// Adds a `MonoBehaviour` named `name` with a script of class `class_name`, like the
+    /// objects found by `--extra-objects`
+    pub fn add_script_object(&mut self, tpk: &Tpk, class_name: &str, name: &str) -> PathId {
+        let script = self.add(
+            tpk,
+            ClassId::MonoScript,
+            json!({ "m_Name": class_name, "m_ClassName": class_name }),
+        );
+        self.add(
+            tpk,
+            ClassId::MonoBehaviour,
+            json!({ "m_Name": name, "m_Script": pptr(script), "m_Enabled": 1 }),
+        )
+    }
+
+    /// Adds the `BuildSettings` of `globalgamemanagers`, listing `scenes` in this order
+    pub fn add_build_settings(&mut self, tpk: &Tpk, scenes: &[&str]) {
+        let scenes = scenes
+            .iter()
+            .map(|scene| format!("Assets/Scenes/{scene}.unity"))
+            .collect::<Vec<_>>();
+        self.add(tpk, ClassId::BuildSettings, json!({ "scenes": scenes }));
+    }
+
+    /// Writes the objects into a serialized file
+    pub fn write(&self, tpk: &Tpk) -> Vec<u8> {
+        let unity_version = unity_version();
+        let common_offset_map = serializedfile::build_common_offset_map(&tpk.inner, &unity_version);
+        let mut builder = SerializedFileBuilder::new(&unity_version, tpk, &common_offset_map, true);
+        for path in &self.externals {
+            builder.add_external_uncached(FileIdentifier::try_from(PathBuf::from(path)).unwrap());
+        }
+        for (path_id, class_id, value) in self.objects.iter().cloned() {
+            match class_id {
+                ClassId::GameObject => builder.add_object_at(path_id, &GameObject(value)),
+                ClassId::Transform => builder.add_object_at(path_id, &Transform(value)),
+                ClassId::MeshRenderer => builder.add_object_at(path_id, &MeshRenderer(value)),
+                ClassId::Material => builder.add_object_at(path_id, &Material(value)),
+                ClassId::MonoBehaviour => builder.add_object_at(path_id, &MonoBehaviour(value)),
+                ClassId::MonoScript => builder.add_object_at(path_id, &MonoScript(value)),
+                ClassId::BuildSettings => builder.add_object_at(path_id, &BuildSettings(value)),
+                _ => unreachable!(),
+            }
+            .unwrap();
+        }
+        let mut data = Vec::new();
+        builder.write(&mut Cursor::new(&mut data)).unwrap();
+        data
+    }
+
+    /// Writes the scene and repacks all of its objects, with the added roots as roots
+    pub fn repack(self, tpk: &Tpk) -> RepackScene<'static> {
+        let data = self.write(tpk);
+
+        let serialized = SerializedFile::from_reader(&mut Cursor::new(data.as_slice())).unwrap();
+        let roots = self
+            .roots
+            .into_iter()
+            .map(|(name, path_id)| {
+                let info = serialized
+                    .objects()
+                    .find(|info| info.m_PathID == path_id)
+                    .unwrap();
+                let object_data = objects::object_data(&data, info);
+                let transform = objects::read_object(&serialized, info, object_data, tpk).unwrap();
+                (name, transform)
+            })
+            .collect();
+
+        RepackScene {
+            original_name: PathBuf::from(format!("{}.unity", self.name)),
+            scene_name: self.name,
+            target: SceneTarget::Scene,
+            keep_objects: serialized.objects().map(|info| info.m_PathID).collect(),
+            serialized,
+            serialized_data: Data::InMemory(data),
+            roots,
+            roots_disabled: false,
+            replacements: Default::default(),
+            monobehaviour_types: Default::default(),
+        }
+    }
+}
+
+/// A game directory in the temp dir with the scenes in the build settings as `level0`, `level1`
+/// etc., removed again on drop
+pub(crate) struct TestGame {
+    dir: PathBuf,
+}
+
+impl TestGame {
+    /// Writes `scenes` and the other `files`. With `reverse`, the files are created in the opposite
+    /// order, which changes the order they are listed in on most file systems.
+    pub fn new(
+        tpk: &Tpk,
+        name: &str,
+        scenes: &[TestScene],
+        files: &[TestScene],
+        reverse: bool,
+    ) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "unity-scene-repacker-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut globalgamemanagers = TestScene::new("globalgamemanagers");
+        let scene_names = scenes
+            .iter()
+            .map(|scene| scene.name.as_str())
+            .collect::<Vec<_>>();
+        globalgamemanagers.add_build_settings(tpk, &scene_names);
+
+        let mut outputs = vec![(
+            "globalgamemanagers".to_owned(),
+            globalgamemanagers.write(tpk),
+        )];
+        outputs.extend(
+            scenes
+                .iter()
+                .enumerate()
+                .map(|(i, scene)| (format!("level{i}"), scene.write(tpk))),
+        );
+        outputs.extend(
+            files
+                .iter()
+                .map(|file| (file.name.clone(), file.write(tpk))),
+        );
+        if reverse {
+            outputs.reverse();
+        }
+        for (filename, data) in outputs {
+            std::fs::write(dir.join(filename), data).unwrap();
+        }
+
+        TestGame { dir }
+    }
+
+    pub fn env(&self) -> Environment {
+        let game_files = GameFiles::probe(&self.dir).unwrap();
+        Environment::new(game_files, tpk())
+    }
+}
+
+impl Drop for TestGame {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
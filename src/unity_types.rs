@@ -0,0 +1,199 @@
+//! Complete definitions of the builtin types we need to rewrite.
+//!
+//! The types in `rabex_env::unity::types` are fine for reading, but when we modify the hierarchy
+//! we have to serialize every field again, in typetree order.
+#![allow(non_snake_case)]
+
+use indexmap::IndexMap;
+use rabex::objects::pptr::PPtr;
+use rabex::objects::{ClassId, ClassIdType};
+use serde::ser::{SerializeStruct, Serializer};
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Vector3f {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Quaternionf {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComponentPair {
+    pub component: PPtr,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameObject {
+    pub m_Component: Vec<ComponentPair>,
+    pub m_Layer: u32,
+    pub m_Name: String,
+    pub m_Tag: u16,
+    pub m_IsActive: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transform {
+    pub m_GameObject: PPtr,
+    pub m_LocalRotation: Quaternionf,
+    pub m_LocalPosition: Vector3f,
+    pub m_LocalScale: Vector3f,
+    pub m_Children: Vec<PPtr>,
+    pub m_Father: PPtr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Vector2f {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RectTransform {
+    pub m_GameObject: PPtr,
+    pub m_LocalRotation: Quaternionf,
+    pub m_LocalPosition: Vector3f,
+    pub m_LocalScale: Vector3f,
+    pub m_Children: Vec<PPtr>,
+    pub m_Father: PPtr,
+    pub m_AnchorMin: Vector2f,
+    pub m_AnchorMax: Vector2f,
+    pub m_AnchoredPosition: Vector2f,
+    pub m_SizeDelta: Vector2f,
+    pub m_Pivot: Vector2f,
+}
+
+impl Vector3f {
+    pub const ONE: Vector3f = Vector3f {
+        x: 1.0,
+        y: 1.0,
+        z: 1.0,
+    };
+
+    pub fn add(self, other: Vector3f) -> Vector3f {
+        Vector3f {
+            x: self.x + other.x,
+            y: self.y + other.y,
+            z: self.z + other.z,
+        }
+    }
+
+    /// Component-wise product
+    pub fn scale(self, other: Vector3f) -> Vector3f {
+        Vector3f {
+            x: self.x * other.x,
+            y: self.y * other.y,
+            z: self.z * other.z,
+        }
+    }
+}
+
+impl Quaternionf {
+    pub const IDENTITY: Quaternionf = Quaternionf {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+        w: 1.0,
+    };
+
+    pub fn mul(self, o: Quaternionf) -> Quaternionf {
+        Quaternionf {
+            x: self.w * o.x + self.x * o.w + self.y * o.z - self.z * o.y,
+            y: self.w * o.y - self.x * o.z + self.y * o.w + self.z * o.x,
+            z: self.w * o.z + self.x * o.y - self.y * o.x + self.z * o.w,
+            w: self.w * o.w - self.x * o.x - self.y * o.y - self.z * o.z,
+        }
+    }
+
+    pub fn rotate(self, v: Vector3f) -> Vector3f {
+        // v' = v + 2w(q × v) + 2(q × (q × v))
+        let q = Vector3f {
+            x: self.x,
+            y: self.y,
+            z: self.z,
+        };
+        let cross = |a: Vector3f, b: Vector3f| Vector3f {
+            x: a.y * b.z - a.z * b.y,
+            y: a.z * b.x - a.x * b.z,
+            z: a.x * b.y - a.y * b.x,
+        };
+        let t = cross(q, v);
+        let t = Vector3f {
+            x: 2.0 * t.x,
+            y: 2.0 * t.y,
+            z: 2.0 * t.z,
+        };
+        let u = cross(q, t);
+        Vector3f {
+            x: v.x + self.w * t.x + u.x,
+            y: v.y + self.w * t.y + u.y,
+            z: v.z + self.w * t.z + u.z,
+        }
+    }
+}
+
+/// Serialized as the 16 `bytes[i]` fields of its typetree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Hash128(pub [u8; 16]);
+
+impl std::fmt::Display for Hash128 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl serde::Serialize for Hash128 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        const FIELDS: [&str; 16] = [
+            "bytes[0]",
+            "bytes[1]",
+            "bytes[2]",
+            "bytes[3]",
+            "bytes[4]",
+            "bytes[5]",
+            "bytes[6]",
+            "bytes[7]",
+            "bytes[8]",
+            "bytes[9]",
+            "bytes[10]",
+            "bytes[11]",
+            "bytes[12]",
+            "bytes[13]",
+            "bytes[14]",
+            "bytes[15]",
+        ];
+        let mut state = serializer.serialize_struct("Hash128", FIELDS.len())?;
+        for (field, byte) in FIELDS.into_iter().zip(self.0) {
+            state.serialize_field(field, &byte)?;
+        }
+        state.end()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AssetBundleManifest {
+    pub m_Name: String,
+    pub AssetBundleNames: IndexMap<i32, String>,
+    pub AssetBundlesWithVariant: Vec<i32>,
+    pub AssetBundleInfos: IndexMap<i32, AssetBundleInfo>,
+}
+
+impl ClassIdType for AssetBundleManifest {
+    const CLASS_ID: ClassId = ClassId::AssetBundleManifest;
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AssetBundleInfo {
+    pub AssetBundleHash: Hash128,
+    pub AssetBundleDependencies: Vec<i32>,
+}
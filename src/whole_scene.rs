@@ -0,0 +1,119 @@
+//! Requesting every root of a scene.
+use anyhow::Result;
+use rabex::files::SerializedFile;
+use rabex::objects::pptr::PathId;
+use rabex::typetree::TypeTreeProvider;
+use rabex_env::unity::types::Transform as PrunedRoot;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::objects;
+use crate::scene_tree::SceneTree;
+use crate::unity_types::GameObject;
+
+/// Roots of the scene, identified by path
+pub(crate) struct WholeScene {
+    pub paths: Vec<String>,
+    /// `(GameObject, original name, temporary name, unique name)` of roots sharing a name with
+    /// another root
+    renamed: Vec<(PathId, String, String, String)>,
+}
+
+/// Collects the paths of all roots except the `excluded` ones.
+///
+/// Pruning looks objects up by path, so roots with the same name are given temporary unique
+/// names in `patches`. They have to be restored with [`WholeScene::restore_names`] afterwards,
+/// which keeps the roots apart by numbering them like unity does for duplicates, e.g. `Enemy (1)`.
+pub(crate) fn whole_scene(
+    file: &SerializedFile,
+    data: &[u8],
+    tpk: &impl TypeTreeProvider,
+    tree: &SceneTree,
+    excluded: &FxHashSet<PathId>,
+    patches: &mut FxHashMap<PathId, Vec<u8>>,
+) -> Result<WholeScene> {
+    let infos = objects::object_infos(file);
+
+    let mut whole_scene = WholeScene {
+        paths: Vec::new(),
+        renamed: Vec::new(),
+    };
+    let root_names = tree
+        .roots
+        .iter()
+        .filter(|root| !excluded.contains(root))
+        .map(|root| tree.nodes[root].name.as_str())
+        .collect::<FxHashSet<_>>();
+    let mut seen_names = FxHashSet::default();
+
+    for &root in &tree.roots {
+        if excluded.contains(&root) {
+            continue;
+        }
+        let node = &tree.nodes[&root];
+
+        if seen_names.insert(node.name.clone()) {
+            whole_scene.paths.push(node.name.clone());
+            continue;
+        }
+
+        let unique_name = (1..)
+            .map(|n| format!("{} ({n})", node.name))
+            .find(|name| !root_names.contains(name.as_str()) && !seen_names.contains(name))
+            .unwrap();
+        seen_names.insert(unique_name.clone());
+
+        let temporary_name = format!("{} ({root})", node.name);
+        let go_info = infos[&node.game_object];
+        let mut go: GameObject = objects::read_object(
+            file,
+            go_info,
+            objects::current_object_data(data, patches, go_info),
+            tpk,
+        )?;
+        go.m_Name.clone_from(&temporary_name);
+        patches.insert(
+            node.game_object,
+            objects::write_object(file, go_info, &go, tpk)?,
+        );
+
+        whole_scene.paths.push(temporary_name.clone());
+        whole_scene.renamed.push((
+            node.game_object,
+            node.name.clone(),
+            temporary_name,
+            unique_name,
+        ));
+    }
+
+    Ok(whole_scene)
+}
+
+impl WholeScene {
+    /// Undoes the renaming of duplicate roots in the replacements, and gives the pruned roots their
+    /// unique name
+    pub fn restore_names(
+        &self,
+        file: &SerializedFile,
+        tpk: &impl TypeTreeProvider,
+        replacements: &mut FxHashMap<PathId, Vec<u8>>,
+        roots: &mut [(String, PrunedRoot)],
+    ) -> Result<()> {
+        let infos = objects::object_infos(file);
+
+        for (go_id, original_name, temporary_name, unique_name) in &self.renamed {
+            if let Some(replacement) = replacements.get(go_id) {
+                let info = infos[go_id];
+                let mut go: GameObject = objects::read_object(file, info, replacement, tpk)?;
+                go.m_Name.clone_from(original_name);
+                replacements.insert(*go_id, objects::write_object(file, info, &go, tpk)?);
+            }
+
+            for (path, _) in roots.iter_mut() {
+                if path == temporary_name {
+                    path.clone_from(unique_name);
+                }
+            }
+        }
+        Ok(())
+    }
+}